@@ -0,0 +1,52 @@
+//! Telegram webhooks, through an API Gateway HTTP API or a Lambda function URL. Try it locally
+//! with `cargo lambda watch` and `cargo lambda invoke webhook --data-file events/webhook.json`.
+
+use lambda_http::{
+    Body, Error, Request, Response,
+    http::StatusCode,
+    run, service_fn,
+    tracing::{self, info, warn},
+};
+use stream_of_conciousness_bot::webhook::{self, SECRET_TOKEN_HEADER};
+
+fn respond(status: StatusCode, body: Body) -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(body)?)
+}
+
+/// Anything but a 2xx makes Telegram send the update again, so only failures that a retry
+/// could fix get one.
+async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
+    let secret = event
+        .headers()
+        .get(SECRET_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok());
+    if !webhook::is_authorized(secret) {
+        warn!("Refusing request without the right secret token");
+        return respond(StatusCode::UNAUTHORIZED, Body::Empty);
+    }
+    let update = match webhook::parse_update(event.body()) {
+        Ok(update) => update,
+        Err(e) => {
+            warn!("Ignoring request: {:?}", e);
+            return respond(StatusCode::BAD_REQUEST, Body::Empty);
+        }
+    };
+    let report = stream_of_conciousness_bot::handle_webhook_update(update).await?;
+    info!("Handled update: {:?}", report);
+    let status = if report.all_failed {
+        StatusCode::INTERNAL_SERVER_ERROR
+    } else {
+        StatusCode::OK
+    };
+    respond(status, serde_json::to_string(&report)?.into())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing::init_default_subscriber();
+
+    run(service_fn(function_handler)).await
+}
@@ -1,17 +1,23 @@
 use aws_lambda_events::event::eventbridge::EventBridgeEvent;
 use lambda_runtime::{
-    run, service_fn,
-    tracing::{self, debug},
-    Error, LambdaEvent,
+    Error, LambdaEvent, run, service_fn,
+    tracing::{self, debug, info},
 };
+use stream_of_conciousness_bot::report::PollReport;
 
-async fn function_handler(event: LambdaEvent<EventBridgeEvent>) -> Result<(), Error> {
+/// The report is serialized as JSON, as the result of the invocation. Like the CLI, it only
+/// fails when nothing could be written, with the report as the error. Otherwise the failed
+/// messages are already in the outbox, and an EventBridge retry would only poll again.
+async fn function_handler(event: LambdaEvent<EventBridgeEvent>) -> Result<PollReport, Error> {
     debug!("Received event: {:?}", event);
 
-    stream_of_conciousness_bot::poll_once_and_update_notion().await?;
+    let report = stream_of_conciousness_bot::poll_once_and_update_notion().await?;
+    info!("Finished polling: {:?}", report);
 
-    // TODO: Return more useful information
-    Ok(())
+    if report.all_failed {
+        return Err(format!("All messages failed: {}", serde_json::to_string(&report)?).into());
+    }
+    Ok(report)
 }
 
 #[tokio::main]
@@ -0,0 +1,130 @@
+//! Photos, videos and documents sent to the bot, which go to the day's page as files.
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use teloxide::{net::Download, prelude::*};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttachmentKind {
+    Image,
+    Video,
+    File,
+}
+
+/// Only what's needed to download it later, the file itself is only fetched when handling.
+#[derive(Clone, Debug)]
+pub struct Attachment {
+    pub kind: AttachmentKind,
+    file_id: String,
+    pub name: String,
+    pub content_type: String,
+    pub caption: Option<String>,
+}
+
+/// The downloaded file.
+#[derive(Serialize, Deserialize)]
+pub struct File {
+    pub kind: AttachmentKind,
+    pub name: String,
+    pub content_type: String,
+    /// Telegram's, to download it again after a restart.
+    #[serde(default)]
+    pub file_id: Option<String>,
+    /// Never saved, as it can be up to 20MB. See [`File::download_again`].
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+impl std::fmt::Debug for File {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({} bytes)", self.name, self.data.len())
+    }
+}
+
+impl File {
+    /// When only the id was kept, like in the outbox.
+    pub async fn download_again(&mut self, bot: &Bot) -> anyhow::Result<()> {
+        if self.data.is_empty() {
+            let file_id = self
+                .file_id
+                .as_ref()
+                .context("No file id to download it again")?;
+            self.data = download(bot, file_id).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Bots can only download files up to 20MB.
+async fn download(bot: &Bot, file_id: &str) -> anyhow::Result<Vec<u8>> {
+    let file = bot.get_file(file_id).await?;
+    let mut data = Vec::with_capacity(file.size as usize);
+    bot.download_file(&file.path, &mut data).await?;
+    Ok(data)
+}
+
+impl Attachment {
+    pub fn from_message(msg: &Message) -> Option<Self> {
+        let (kind, file, name, content_type) = if let Some(photo) = msg.photo() {
+            // Sizes are in increasing order.
+            let photo = photo.last()?;
+            (
+                AttachmentKind::Image,
+                &photo.file,
+                format!("{}.jpg", photo.file.unique_id),
+                "image/jpeg".to_string(),
+            )
+        } else if let Some(video) = msg.video() {
+            (
+                AttachmentKind::Video,
+                &video.file,
+                video
+                    .file_name
+                    .clone()
+                    .unwrap_or_else(|| format!("{}.mp4", video.file.unique_id)),
+                video
+                    .mime_type
+                    .as_ref()
+                    .map_or("video/mp4".to_string(), ToString::to_string),
+            )
+        } else if let Some(document) = msg.document() {
+            let content_type = document
+                .mime_type
+                .as_ref()
+                .map_or("application/octet-stream".to_string(), ToString::to_string);
+            let kind = if content_type.starts_with("image/") {
+                AttachmentKind::Image
+            } else {
+                AttachmentKind::File
+            };
+            (
+                kind,
+                &document.file,
+                document
+                    .file_name
+                    .clone()
+                    .unwrap_or_else(|| document.file.unique_id.clone()),
+                content_type,
+            )
+        } else {
+            return None;
+        };
+        Some(Self {
+            kind,
+            file_id: file.id.clone(),
+            name,
+            content_type,
+            caption: msg.caption().map(ToString::to_string),
+        })
+    }
+
+    pub async fn download(&self, bot: &Bot) -> anyhow::Result<File> {
+        Ok(File {
+            kind: self.kind,
+            name: self.name.clone(),
+            content_type: self.content_type.clone(),
+            file_id: Some(self.file_id.clone()),
+            data: download(bot, &self.file_id).await?,
+        })
+    }
+}
@@ -1,7 +1,54 @@
 use chrono::{DateTime, NaiveDate, NaiveTime, Timelike, Utc};
-use teloxide::{utils::command::BotCommands, Bot};
+use chrono_tz::Tz;
+use teloxide::{
+    Bot,
+    types::{ChatId, MessageId},
+    utils::command::BotCommands,
+};
 
-use crate::notion_manager::{InnerCommand, NotionCommand, NotionManager, HOUR_CUT_TO_NEXT_DAY};
+use crate::{
+    attachment::Attachment,
+    formatting::Formatting,
+    journal::{JournalBackend, Journals, TextEntry, normalize_name},
+    ledger::Ledger,
+    messages::Journaled,
+    notion_manager::{InnerCommand, NotionCommand},
+    reply::{Replies, Reply},
+    report::{PollReport, UserReport},
+    search::Search,
+    settings::InvalidSetting,
+    stats::Period,
+};
+
+pub enum Content {
+    Command(Command),
+    Attachment(Attachment),
+}
+
+pub struct IncomingMessage {
+    pub content: Content,
+    pub username: String,
+    pub date: DateTime<Utc>,
+    pub chat_id: ChatId,
+    /// If set, the day this is about instead of the one from `date`.
+    pub day: Option<NaiveDate>,
+    /// To skip it if it was already applied, see [`crate::ledger`].
+    pub update_id: u32,
+    /// The message with the text, to mark the entries it becomes.
+    pub message_id: Option<MessageId>,
+    /// Of the text, or the caption of an attachment.
+    pub formatting: Vec<Formatting>,
+    /// A new version of a message handled before, see [`crate::messages`].
+    pub edited: bool,
+}
+
+/// What happened with the commands of a batch, including settings.
+pub struct Handled {
+    pub report: PollReport,
+    /// Only journal commands, settings are not retried. Neither are some journal ones, see
+    /// [`InnerCommand::is_retried`].
+    pub failed: Vec<(NotionCommand, anyhow::Error)>,
+}
 
 #[derive(BotCommands, Clone, Debug)]
 #[command(
@@ -13,21 +60,56 @@ pub enum Command {
     Mood(u8),
     #[command(description = "people you mention", aliases = ["people", "mention"])]
     Person(String),
+    #[command(description = "your timezone, like Europe/London.", aliases = ["tz"])]
+    Timezone(String),
+    #[command(
+        description = "hour (0-23) when your day starts, earlier messages go to the previous day.",
+        aliases = ["rollover"]
+    )]
+    DayStart(u32),
+    #[command(description = "adds someone new to your people, and mentions them.")]
+    AddPerson(String),
+    #[command(
+        description = "a nickname, like /alias Bia = Beatriz Souza. Without a name after =, removes it. Alone, lists them."
+    )]
+    Alias(String),
+    #[command(description = "takes back your last mood, entry, file or people.")]
+    Undo,
+    #[command(description = "shows what your journal has for today.")]
+    Today,
+    #[command(description = "shows what your journal has for a day, like /day 2026-10-01.")]
+    Day(String),
+    #[command(description = "finds past entries, like /search coffee since:2026-10-01.")]
+    Search(String),
+    #[command(description = "your mood over the last week, month or year, with a chart.")]
+    Stats(String),
+    #[command(
+        description = "asks your mood every day at that time, like /reminder 21:00. /reminder off stops it."
+    )]
+    Reminder(String),
     #[command(hide)]
     Text(String),
 }
 
+fn split_people(people: &str) -> Vec<String> {
+    people.split(',').map(|s| s.trim().to_string()).collect()
+}
+
 impl Command {
     pub fn parse_or_text(text: String) -> Self {
         Self::parse(&text, "").unwrap_or(Self::Text(text))
     }
 
-    /// Multiple fixes to the date. First, considers the correct timezone.
-    /// Then, considers the previous day if the time is before 6am (super reasonable for me).
-    fn fix_date(date: DateTime<Utc>) -> (NaiveDate, NaiveTime) {
-        const CORRECT_TIMEZONE: chrono_tz::Tz = chrono_tz::America::Sao_Paulo;
-        let date = date.with_timezone(&CORRECT_TIMEZONE);
-        let datetime = if date.hour() < HOUR_CUT_TO_NEXT_DAY {
+    /// Multiple fixes to the date. First, considers the user's timezone.
+    /// Then, considers the previous day if the time is before the user's day start
+    /// (6am by default, super reasonable for me).
+    pub fn fix_date(
+        date: DateTime<Utc>,
+        timezone: Tz,
+        day_start_hour: u32,
+    ) -> (NaiveDate, NaiveTime) {
+        let date = date.with_timezone(&timezone);
+        let datetime = if date.hour() < day_start_hour {
             date - chrono::Duration::days(1)
         } else {
             date
@@ -37,41 +119,437 @@ impl Command {
         (datetime.date(), datetime.time())
     }
 
-    pub async fn handle(
-        _bot: &Bot,
-        cmds: Vec<(Command, String, DateTime<Utc>)>,
-        notion: &mut NotionManager,
-    ) -> anyhow::Result<(usize, usize)> {
-        let mut success = 0;
-        let mut total = 0;
+    /// Invalid settings are ignored, like other bad arguments.
+    fn setting_or_log(
+        username: &str,
+        update_id: u32,
+        result: anyhow::Result<()>,
+        description: String,
+        reply: &mut Reply,
+        ledger: &mut Ledger,
+        report: &mut UserReport,
+    ) {
+        match result {
+            Ok(()) => {
+                reply.setting(description);
+                ledger.record_or_log(username, [update_id]);
+                report.success();
+            }
+            Err(e) => match e.downcast_ref::<InvalidSetting>() {
+                Some(invalid) => Self::ignore(
+                    username,
+                    update_id,
+                    format!("{}: {}", description, invalid),
+                    reply,
+                    ledger,
+                    report,
+                ),
+                None => {
+                    log::error!("Error changing settings of {}: {:?}", username, e);
+                    reply.failure(description.clone(), &e);
+                    report.failure(description, &e);
+                }
+            },
+        }
+    }
+
+    /// Like a bad argument. The user is told why, and it's not retried.
+    fn ignore(
+        username: &str,
+        update_id: u32,
+        why: String,
+        reply: &mut Reply,
+        ledger: &mut Ledger,
+        report: &mut UserReport,
+    ) {
+        log::info!("Ignoring {} from {}", why, username);
+        reply.ignored(why);
+        ledger.record_or_log(username, [update_id]);
+        report.skipped += 1;
+    }
+
+    /// Returns the result with what to reply.
+    fn alias<B>(
+        journals: &mut Journals<B>,
+        username: &str,
+        alias: &str,
+    ) -> (anyhow::Result<()>, String) {
+        match alias.split_once('=') {
+            None if alias.trim().is_empty() => {
+                let aliases = journals.settings.aliases(username);
+                let list = aliases
+                    .iter()
+                    .map(|(alias, person)| format!("{} = {}", alias, person))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                (Ok(()), format!("aliases: {}", list))
+            }
+            None => (
+                Err(InvalidSetting("Use /alias Nickname = Full Name".to_string()).into()),
+                format!("alias {}", alias),
+            ),
+            Some((alias, person)) => {
+                let description = if person.trim().is_empty() {
+                    format!("removed alias {}", alias.trim())
+                } else {
+                    format!("alias {} = {}", alias.trim(), person.trim())
+                };
+                (
+                    journals.settings.set_alias(username, alias, person),
+                    description,
+                )
+            }
+        }
+    }
+
+    /// What changes in the journal with an edited message, or why it's ignored. The date is
+    /// the one the message went to, even if the settings changed since.
+    fn edit<B>(
+        journals: &mut Journals<B>,
+        username: &str,
+        marker: Option<String>,
+        time: NaiveTime,
+        cmd: Self,
+        formatting: Vec<Formatting>,
+    ) -> Result<(NaiveDate, InnerCommand), String> {
+        let marker = marker.ok_or("edit of an unknown message")?;
+        let original = journals
+            .messages
+            .get(username, &marker)
+            .cloned()
+            .ok_or("edit of a message not in the journal")?;
+        let date = original.date;
+        let inner = match (cmd, original.content) {
+            (Self::Text(text), Journaled::Text(ids)) => InnerCommand::EditText(
+                TextEntry {
+                    text,
+                    time,
+                    marker: Some(marker),
+                    formatting,
+                },
+                ids,
+            ),
+            (Self::Mood(mood), Journaled::Mood(_)) => {
+                journals
+                    .messages
+                    .record_or_log(username, &marker, date, Journaled::Mood(mood));
+                // Only the last mood of the day counts.
+                InnerCommand::Mood(
+                    journals
+                        .messages
+                        .latest_mood(username, date)
+                        .unwrap_or(mood),
+                )
+            }
+            (Self::Person(people), Journaled::People(old)) => {
+                let new = split_people(&people);
+                let removed = old
+                    .into_iter()
+                    .filter(|name| {
+                        !new.iter()
+                            .any(|n| normalize_name(n) == normalize_name(name))
+                            && !journals
+                                .messages
+                                .mentioned_elsewhere(username, date, &marker, name)
+                    })
+                    .collect();
+                journals.messages.record_or_log(
+                    username,
+                    &marker,
+                    date,
+                    Journaled::People(new.clone()),
+                );
+                InnerCommand::EditPeople {
+                    removed,
+                    added: new,
+                }
+            }
+            _ => return Err("edit that changes what kind of message it is".to_string()),
+        };
+        Ok((date, inner))
+    }
+
+    /// Also replies to each chat with what happened.
+    pub async fn handle<B: JournalBackend>(
+        bot: &Bot,
+        msgs: Vec<IncomingMessage>,
+        journals: &mut Journals<B>,
+    ) -> anyhow::Result<Handled> {
+        let mut handled = Handled {
+            report: PollReport::default(),
+            failed: vec![],
+        };
+        let mut replies = Replies::default();
+        // Edits go last, so an edited message in the same batch is already in the journal.
+        let (edits, msgs): (Vec<_>, Vec<_>) = msgs.into_iter().partition(|msg| msg.edited);
+        for msgs in [msgs, edits] {
+            Self::handle_batch(bot, msgs, journals, &mut handled, &mut replies).await;
+        }
+        replies.send(bot).await;
+        Ok(handled)
+    }
+
+    async fn handle_batch<B: JournalBackend>(
+        bot: &Bot,
+        msgs: Vec<IncomingMessage>,
+        journals: &mut Journals<B>,
+        handled: &mut Handled,
+        replies: &mut Replies,
+    ) {
         let mut pending_cmd = None;
-        for (cmd, username, date) in cmds {
-            let (date, time) = Self::fix_date(date);
-            let inner = match cmd {
-                Self::Mood(mood) => InnerCommand::Mood(mood),
-                Self::Text(text) => InnerCommand::Text(vec![(text, time)]),
-                Self::Person(person) => {
-                    InnerCommand::People(person.split(',').map(|s| s.trim().to_string()).collect())
+        let mut to_execute = vec![];
+        for IncomingMessage {
+            content,
+            username,
+            date,
+            chat_id,
+            day,
+            update_id,
+            message_id,
+            formatting,
+            edited,
+        } in msgs
+        {
+            if journals.ledger.is_applied(&username, update_id) {
+                log::info!("Skipping update {} already applied", update_id);
+                continue;
+            }
+            let marker = message_id.map(|id| format!("{}/{}", chat_id, id.0));
+            let (date, time) = Self::fix_date(
+                date,
+                journals.settings.timezone(&username),
+                journals.settings.day_start_hour(&username),
+            );
+            let mut date = day.unwrap_or(date);
+            let inner = match content {
+                Content::Command(cmd) if edited => {
+                    match Self::edit(journals, &username, marker, time, cmd, formatting) {
+                        Ok((original_date, inner)) => {
+                            date = original_date;
+                            inner
+                        }
+                        Err(why) => {
+                            Self::ignore(
+                                &username,
+                                update_id,
+                                why,
+                                replies.chat(chat_id),
+                                &mut journals.ledger,
+                                handled.report.user(&username),
+                            );
+                            continue;
+                        }
+                    }
                 }
+                Content::Attachment(attachment) => match attachment.download(bot).await {
+                    Ok(file) => InnerCommand::File(
+                        file,
+                        attachment.caption.map(|text| TextEntry {
+                            text,
+                            time,
+                            marker,
+                            formatting,
+                        }),
+                    ),
+                    Err(e) => {
+                        log::error!("Failed to download {:?}: {:?}", attachment, e);
+                        let what = format!("downloading {}", attachment.name);
+                        replies.chat(chat_id).failure(what.clone(), &e);
+                        handled.report.user(&username).failure(what, &e);
+                        continue;
+                    }
+                },
+                Content::Command(cmd) => match cmd {
+                    Self::Mood(mood) => {
+                        if let Some(marker) = &marker {
+                            journals.messages.record_or_log(
+                                &username,
+                                marker,
+                                date,
+                                Journaled::Mood(mood),
+                            );
+                        }
+                        InnerCommand::Mood(mood)
+                    }
+                    // Written before, but the ledger wasn't saved, like after a crash.
+                    Self::Text(_)
+                        if marker.as_ref().is_some_and(|marker| {
+                            journals.messages.get(&username, marker).is_some()
+                        }) =>
+                    {
+                        log::info!("Skipping update {} already journaled", update_id);
+                        journals.ledger.record_or_log(&username, [update_id]);
+                        continue;
+                    }
+                    Self::Text(text) => InnerCommand::Text(vec![TextEntry {
+                        text,
+                        time,
+                        marker,
+                        formatting,
+                    }]),
+                    Self::Person(person) => {
+                        let people = split_people(&person);
+                        if let Some(marker) = &marker {
+                            journals.messages.record_or_log(
+                                &username,
+                                marker,
+                                date,
+                                Journaled::People(people.clone()),
+                            );
+                        }
+                        InnerCommand::People(people)
+                    }
+                    Self::AddPerson(name) => InnerCommand::AddPerson(name.trim().to_string()),
+                    Self::Undo => InnerCommand::Undo,
+                    Self::Today => InnerCommand::ShowDay,
+                    Self::Search(query) => match Search::parse(&query) {
+                        Ok(search) => InnerCommand::Search(search),
+                        Err(why) => {
+                            Self::ignore(
+                                &username,
+                                update_id,
+                                why,
+                                replies.chat(chat_id),
+                                &mut journals.ledger,
+                                handled.report.user(&username),
+                            );
+                            continue;
+                        }
+                    },
+                    Self::Stats(period) => match Period::parse(&period) {
+                        Ok(period) => InnerCommand::Stats(period),
+                        Err(why) => {
+                            Self::ignore(
+                                &username,
+                                update_id,
+                                why,
+                                replies.chat(chat_id),
+                                &mut journals.ledger,
+                                handled.report.user(&username),
+                            );
+                            continue;
+                        }
+                    },
+                    Self::Day(day) => match NaiveDate::parse_from_str(day.trim(), "%Y-%m-%d") {
+                        Ok(day) => {
+                            date = day;
+                            InnerCommand::ShowDay
+                        }
+                        Err(_) => {
+                            Self::ignore(
+                                &username,
+                                update_id,
+                                format!("/day {}, use /day 2026-10-01", day.trim()),
+                                replies.chat(chat_id),
+                                &mut journals.ledger,
+                                handled.report.user(&username),
+                            );
+                            continue;
+                        }
+                    },
+                    Self::Timezone(timezone) => {
+                        Self::setting_or_log(
+                            &username,
+                            update_id,
+                            journals.settings.set_timezone(&username, &timezone),
+                            format!("timezone {}", timezone),
+                            replies.chat(chat_id),
+                            &mut journals.ledger,
+                            handled.report.user(&username),
+                        );
+                        continue;
+                    }
+                    Self::Alias(alias) => {
+                        let (result, description) = Self::alias(journals, &username, &alias);
+                        Self::setting_or_log(
+                            &username,
+                            update_id,
+                            result,
+                            description,
+                            replies.chat(chat_id),
+                            &mut journals.ledger,
+                            handled.report.user(&username),
+                        );
+                        continue;
+                    }
+                    Self::Reminder(reminder) => {
+                        journals.reminders.set_chat_or_log(&username, chat_id);
+                        let (result, description) = if reminder.trim().is_empty() {
+                            let description = match journals.settings.reminder(&username) {
+                                Some(time) => format!("reminder at {}", time.format("%H:%M")),
+                                None => "no reminder".to_string(),
+                            };
+                            (Ok(()), description)
+                        } else {
+                            (
+                                journals.settings.set_reminder(&username, &reminder),
+                                format!("reminder {}", reminder.trim()),
+                            )
+                        };
+                        Self::setting_or_log(
+                            &username,
+                            update_id,
+                            result,
+                            description,
+                            replies.chat(chat_id),
+                            &mut journals.ledger,
+                            handled.report.user(&username),
+                        );
+                        continue;
+                    }
+                    Self::DayStart(hour) => {
+                        Self::setting_or_log(
+                            &username,
+                            update_id,
+                            journals.settings.set_day_start_hour(&username, hour),
+                            format!("day start at {}h", hour),
+                            replies.chat(chat_id),
+                            &mut journals.ledger,
+                            handled.report.user(&username),
+                        );
+                        continue;
+                    }
+                },
             };
             let new_cmd = NotionCommand {
                 inner,
                 username,
+                chat_id,
                 date,
+                update_ids: vec![update_id],
             };
             match NotionCommand::try_merge(pending_cmd.take(), new_cmd) {
                 Ok(cmd) => pending_cmd = Some(cmd),
                 Err((old, new)) => {
-                    total += 1;
-                    success += old.execute_or_log(notion).await as usize;
+                    to_execute.push(old);
                     pending_cmd = Some(new);
                 }
             }
         }
-        if let Some(cmd) = pending_cmd {
-            total += 1;
-            success += cmd.execute_or_log(notion).await as usize;
+        to_execute.extend(pending_cmd);
+        for cmd in to_execute {
+            let result = cmd
+                .execute_or_log(journals, replies.chat(cmd.chat_id))
+                .await;
+            let report = handled.report.user(&cmd.username);
+            match result {
+                Ok(()) => {
+                    report.success();
+                    journals
+                        .ledger
+                        .record_or_log(&cmd.username, cmd.update_ids.iter().copied());
+                }
+                Err(e) if !cmd.inner.is_retried() => {
+                    report.failure(&cmd, &e);
+                    journals
+                        .ledger
+                        .record_or_log(&cmd.username, cmd.update_ids.iter().copied());
+                }
+                Err(e) => {
+                    report.failure(&cmd, &e);
+                    handled.failed.push((cmd, e));
+                }
+            }
         }
-        Ok((success, total))
     }
 }
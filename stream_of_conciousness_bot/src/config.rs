@@ -0,0 +1,233 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use chrono_tz::Tz;
+use serde::Deserialize;
+
+use crate::reminders::Reminder;
+
+/// Loaded from CONFIG_FILE (defaults to config.toml, it's fine if it doesn't exist).
+/// See config.example.toml for all options.
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub users: Vec<UserConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    pub username: String,
+    /// Safer than the username, which can be changed.
+    pub telegram_id: Option<u64>,
+    notion_token: Option<String>,
+    notion_token_file: Option<PathBuf>,
+    pub database_id: Option<String>,
+    pub timezone: Option<Tz>,
+    /// Messages before this hour go to the previous day.
+    pub day_start_hour: Option<u32>,
+    /// Nicknames, like `Bia = "Beatriz Souza"`.
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    /// When to ask for the mood, like `"21:00"`.
+    pub reminder: Option<Reminder>,
+    #[serde(default)]
+    pub properties: PropertyNames,
+}
+
+/// Names of the properties in the Notion database.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PropertyNames {
+    pub mood: String,
+    pub tags: String,
+    pub date: String,
+    pub title: String,
+    pub people: String,
+    /// Not a property, but the tag all the bot's pages have.
+    pub stream_of_consciousness: String,
+}
+
+impl Default for PropertyNames {
+    fn default() -> Self {
+        Self {
+            mood: "Mood".to_string(),
+            tags: "Tags".to_string(),
+            date: "Date".to_string(),
+            title: "title".to_string(), // Default and lowercase in notion
+            people: "Pessoas".to_string(),
+            stream_of_consciousness: "Stream of conciousness".to_string(),
+        }
+    }
+}
+
+impl UserConfig {
+    fn new(username: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            telegram_id: None,
+            notion_token: None,
+            notion_token_file: None,
+            database_id: None,
+            timezone: None,
+            day_start_hour: None,
+            aliases: BTreeMap::new(),
+            reminder: None,
+            properties: PropertyNames::default(),
+        }
+    }
+
+    pub fn notion_token(&self) -> anyhow::Result<String> {
+        match (&self.notion_token, &self.notion_token_file) {
+            (Some(token), None) => Ok(token.clone()),
+            (None, Some(path)) => Ok(std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read token file {}", path.display()))?
+                .trim()
+                .to_string()),
+            (None, None) => anyhow::bail!("No notion_token or notion_token_file"),
+            (Some(_), Some(_)) => anyhow::bail!("Both notion_token and notion_token_file"),
+        }
+    }
+
+    pub fn database_id(&self) -> anyhow::Result<&str> {
+        self.database_id.as_deref().context("No database_id")
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.username.is_empty() && !self.username.contains(char::is_whitespace),
+            "Invalid username {:?}",
+            self.username
+        );
+        anyhow::ensure!(self.telegram_id != Some(0), "Invalid telegram_id 0");
+        if let Some(db_id) = &self.database_id {
+            let hex: String = db_id.chars().filter(|&c| c != '-').collect();
+            anyhow::ensure!(
+                hex.len() == 32 && hex.chars().all(|c| c.is_ascii_hexdigit()),
+                "Invalid database_id {:?}, should be 32 hex digits",
+                db_id
+            );
+        }
+        if let Some(hour) = self.day_start_hour {
+            anyhow::ensure!(hour < 24, "Invalid day_start_hour {}", hour);
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn load() -> anyhow::Result<Self> {
+        let path = PathBuf::from(
+            std::env::var("CONFIG_FILE").unwrap_or_else(|_| "config.toml".to_string()),
+        );
+        let config = if path.exists() {
+            Self::from_file(&path)?
+        } else {
+            Self::default()
+        };
+        config.with_env(|var| std::env::var(var).ok())
+    }
+
+    /// Overrides with the environment, as given by `env`, then checks everything.
+    pub fn with_env(mut self, env: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        self.override_from_env(&env)?;
+        self.validate()?;
+        Ok(self)
+    }
+
+    fn from_file(path: &Path) -> anyhow::Result<Self> {
+        toml::from_str(
+            &std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read config file {}", path.display()))?,
+        )
+        .with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// TELEGRAM_USERNAMES, NOTION_TOKENS, NOTION_DATABASE_IDS, TIMEZONES and DAY_START_HOURS are
+    /// comma separated, in the same order. They override or add to the users in the file.
+    /// Empty values keep what is in the file.
+    fn override_from_env(&mut self, env: &impl Fn(&str) -> Option<String>) -> anyhow::Result<()> {
+        let Some(usernames) = env("TELEGRAM_USERNAMES") else {
+            return Ok(());
+        };
+        let usernames: Vec<&str> = usernames.split(',').map(str::trim).collect();
+        let users = usernames.len();
+        let tokens = per_user_env(env, "NOTION_TOKENS", users, |t| Ok(t.to_string()))?;
+        let db_ids = per_user_env(env, "NOTION_DATABASE_IDS", users, |id| Ok(id.to_string()))?;
+        let timezones = per_user_env(env, "TIMEZONES", users, |tz| {
+            tz.parse()
+                .map_err(|e| anyhow::anyhow!("Invalid timezone {:?}: {}", tz, e))
+        })?;
+        let day_start_hours = per_user_env(env, "DAY_START_HOURS", users, |h| Ok(h.parse()?))?;
+        for (i, username) in usernames.into_iter().enumerate() {
+            let user = if let Some(user) = self.users.iter_mut().find(|u| u.username == username) {
+                user
+            } else {
+                self.users.push(UserConfig::new(username));
+                self.users.last_mut().unwrap()
+            };
+            if let Some(token) = tokens[i].clone() {
+                user.notion_token = Some(token);
+                user.notion_token_file = None;
+            }
+            user.database_id = db_ids[i].clone().or(user.database_id.take());
+            user.timezone = timezones[i].or(user.timezone);
+            user.day_start_hour = day_start_hours[i].or(user.day_start_hour);
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.users.is_empty(),
+            "No users configured, use a config file or TELEGRAM_USERNAMES"
+        );
+        let mut usernames = BTreeSet::new();
+        let mut ids = BTreeSet::new();
+        for user in &self.users {
+            user.validate()
+                .with_context(|| format!("Invalid config for user {}", user.username))?;
+            anyhow::ensure!(
+                usernames.insert(user.username.to_lowercase()),
+                "Duplicate username {}",
+                user.username
+            );
+            if let Some(id) = user.telegram_id {
+                anyhow::ensure!(ids.insert(id), "Duplicate telegram_id {}", id);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// If the variable is set, it must have exactly one value per user.
+fn per_user_env<T>(
+    env: &impl Fn(&str) -> Option<String>,
+    var: &str,
+    users: usize,
+    parse: impl Fn(&str) -> anyhow::Result<T>,
+) -> anyhow::Result<Vec<Option<T>>> {
+    let Some(values) = env(var) else {
+        return Ok((0..users).map(|_| None).collect());
+    };
+    let values: Vec<&str> = values.split(',').map(str::trim).collect();
+    anyhow::ensure!(
+        values.len() == users,
+        "{} has {} values, but TELEGRAM_USERNAMES has {}",
+        var,
+        values.len(),
+        users
+    );
+    values
+        .into_iter()
+        .map(|v| {
+            (!v.is_empty())
+                .then(|| parse(v).with_context(|| format!("Invalid value in {}", var)))
+                .transpose()
+        })
+        .collect()
+}
@@ -0,0 +1,90 @@
+//! Long running mode, for when it's not run on a schedule. Messages are handled as they arrive,
+//! and the journals (with their caches) are kept between batches.
+
+use std::time::Duration;
+
+use teloxide::{prelude::*, requests::HasPayload};
+use tokio::signal::unix::{SignalKind, signal};
+
+use crate::{
+    get_updates, handle_updates,
+    journal::{JournalBackend, Journals},
+    outbox, reminders,
+};
+
+/// How long Telegram holds each request waiting for messages.
+const LONG_POLL_SECONDS: u32 = 30;
+/// When Telegram or Notion are failing, wait a bit before trying again.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// A batch is always finished before stopping, so nothing is written twice.
+pub async fn run<B: JournalBackend>(bot: &Bot, mut journals: Journals<B>) -> anyhow::Result<()> {
+    log::info!("Running as a daemon, waiting for messages...");
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut off = None;
+    let mut delay = Duration::ZERO;
+    loop {
+        let updates = tokio::select! {
+            _ = terminate.recv() => break,
+            _ = tokio::signal::ctrl_c() => break,
+            updates = async {
+                tokio::time::sleep(delay).await;
+                get_updates(bot, off, LONG_POLL_SECONDS).await
+            } => updates,
+        };
+        delay = Duration::ZERO;
+        if let Err(e) = outbox::retry_due(bot, &mut journals).await {
+            log::error!("Failed to retry the outbox: {:?}", e);
+        }
+        reminders::send_due(bot, &mut journals).await;
+        let updates = match updates {
+            Ok(updates) => updates,
+            Err(e) => {
+                log::error!("Failed to get updates: {:?}", e);
+                delay = RETRY_DELAY;
+                continue;
+            }
+        };
+        let Some(next_off) = updates.last().map(|u| u.id.as_offset()) else {
+            continue;
+        };
+        match handle_updates(bot, &mut journals, updates).await {
+            // Same as when polling once: if everything failed, Notion is likely down, so
+            // don't ack and try the same messages again later.
+            Ok(report) if report.none_succeeded() => {
+                log::error!("All messages failed, will try again later.");
+                delay = RETRY_DELAY;
+            }
+            Err(e) => {
+                log::error!("Failed to handle messages, will try again later: {:?}", e);
+                delay = RETRY_DELAY;
+            }
+            Ok(report) => {
+                if report.has_failures() {
+                    log::warn!(
+                        "Some errors occurred, {}/{} succeeded.",
+                        report.successes(),
+                        report.commands()
+                    );
+                }
+                off = Some(next_off);
+            }
+        }
+    }
+    log::info!("Stopping daemon.");
+    ack(bot, off).await
+}
+
+/// Updates are only acked when asking for the next ones, so do that without handling them.
+async fn ack(bot: &Bot, off: Option<i32>) -> anyhow::Result<()> {
+    if off.is_some() {
+        bot.get_updates()
+            .with_payload_mut(|p| {
+                p.offset = off;
+                p.limit = Some(1);
+            })
+            .send()
+            .await?;
+    }
+    Ok(())
+}
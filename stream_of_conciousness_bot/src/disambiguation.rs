@@ -0,0 +1,101 @@
+//! When a person is ambiguous, we ask which one it was with an inline keyboard.
+//! Everything needed is in the keyboard itself, so nothing has to be stored meanwhile: the
+//! callback data is the day and the index of the button, and the buttons are the names.
+
+use chrono::{NaiveDate, Utc};
+use teloxide::{
+    prelude::*,
+    types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup},
+};
+
+use crate::commands::{Command, Content, IncomingMessage};
+
+const NEW_PERSON: &str = "➕ New person: ";
+
+pub async fn ask(
+    bot: &Bot,
+    chat_id: ChatId,
+    date: NaiveDate,
+    name: &str,
+    candidates: &[String],
+) -> anyhow::Result<()> {
+    let buttons = candidates
+        .iter()
+        .cloned()
+        .chain(std::iter::once(format!("{}{}", NEW_PERSON, name)))
+        .enumerate()
+        .map(|(i, text)| vec![InlineKeyboardButton::callback(text, callback_data(date, i))]);
+    bot.send_message(chat_id, format!("Who is \"{}\" on {}?", name, date))
+        .reply_markup(InlineKeyboardMarkup::new(buttons))
+        .await?;
+    Ok(())
+}
+
+pub fn callback_data(date: NaiveDate, index: usize) -> String {
+    format!("{} {}", date, index)
+}
+
+pub fn parse_callback_data(data: &str) -> anyhow::Result<(NaiveDate, usize)> {
+    let (date, index) = data
+        .split_once(' ')
+        .ok_or_else(|| anyhow::anyhow!("Invalid callback data: {:?}", data))?;
+    Ok((date.parse()?, index.parse()?))
+}
+
+/// Stops the loading animation on the button. Telegram refuses it for queries answered late,
+/// like when replaying a batch, which is no reason to lose the choice.
+pub(crate) async fn answer_or_log(bot: &Bot, query: &CallbackQuery) {
+    if let Err(e) = bot.answer_callback_query(query.id.clone()).await {
+        log::warn!("Failed to answer callback {}: {:?}", query.id, e);
+    }
+}
+
+/// Replaces the keyboard with the choice, so it isn't chosen twice. Also best effort, on a
+/// replay the message might already say that.
+pub(crate) async fn edit_or_log(bot: &Bot, message: &Message, text: String) {
+    if let Err(e) = bot
+        .edit_message_text(message.chat.id, message.id, text)
+        .await
+    {
+        log::warn!("Failed to edit keyboard message {}: {:?}", message.id, e);
+    }
+}
+
+/// Turns the chosen button into a command for the day it was asked about.
+pub async fn handle_callback(
+    bot: &Bot,
+    query: CallbackQuery,
+    username: String,
+    update_id: u32,
+) -> anyhow::Result<IncomingMessage> {
+    answer_or_log(bot, &query).await;
+    let message = query
+        .message
+        .as_ref()
+        .and_then(|m| m.regular_message())
+        .ok_or_else(|| anyhow::anyhow!("Keyboard message is too old"))?;
+    let (day, index) = parse_callback_data(query.data.as_deref().unwrap_or_default())?;
+    let chosen = message
+        .reply_markup()
+        .and_then(|markup| markup.inline_keyboard.get(index))
+        .and_then(|row| row.first())
+        .map(|button| button.text.clone())
+        .ok_or_else(|| anyhow::anyhow!("No button {} in keyboard", index))?;
+    let cmd = if let Some(name) = chosen.strip_prefix(NEW_PERSON) {
+        Command::AddPerson(name.to_string())
+    } else {
+        Command::Person(chosen.clone())
+    };
+    edit_or_log(bot, message, format!("Chosen: {}", chosen)).await;
+    Ok(IncomingMessage {
+        content: Content::Command(cmd),
+        username,
+        date: Utc::now(),
+        chat_id: message.chat.id,
+        day: Some(day),
+        update_id,
+        message_id: None,
+        formatting: vec![],
+        edited: false,
+    })
+}
@@ -0,0 +1,85 @@
+//! The whole journal of a user out of the backend, for backups or moving somewhere else.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::journal::{Day, JournalBackend};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// One file per day, with front matter.
+    Markdown,
+    /// A single file, one day per line.
+    JsonLines,
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "markdown" | "md" => Ok(Self::Markdown),
+            "jsonl" | "json" => Ok(Self::JsonLines),
+            other => anyhow::bail!("Unknown export format {:?}, use markdown or jsonl", other),
+        }
+    }
+}
+
+/// Strings are quoted as JSON, which is also valid YAML, so names with colons or quotes are
+/// fine. Each line of the day is a paragraph.
+pub fn markdown(day: &Day) -> anyhow::Result<String> {
+    let mut md = format!("---\ndate: {}\n", day.date);
+    if let Some(mood) = day.mood {
+        md += &format!("mood: {}\n", mood);
+    }
+    md += &format!("people: {}\n", serde_json::to_string(&day.people)?);
+    md += &format!("tags: {}\n", serde_json::to_string(&day.tags)?);
+    md += "---\n";
+    for line in &day.lines {
+        md += &format!("\n{}\n", line);
+    }
+    Ok(md)
+}
+
+/// Markdown goes to `{dir}/{username}/{date}.md`, JSON lines to `{dir}/{username}.jsonl`.
+/// Returns where it was written.
+pub fn write(days: &[Day], format: Format, dir: &Path, username: &str) -> anyhow::Result<PathBuf> {
+    let path = match format {
+        Format::Markdown => {
+            let path = dir.join(username);
+            std::fs::create_dir_all(&path)?;
+            for day in days {
+                let file = path.join(format!("{}.md", day.date));
+                std::fs::write(&file, markdown(day)?)
+                    .with_context(|| format!("Failed to write {}", file.display()))?;
+            }
+            path
+        }
+        Format::JsonLines => {
+            std::fs::create_dir_all(dir)?;
+            let path = dir.join(format!("{}.jsonl", username));
+            let lines = days
+                .iter()
+                .map(|day| Ok(serde_json::to_string(day)? + "\n"))
+                .collect::<anyhow::Result<String>>()?;
+            std::fs::write(&path, lines)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            path
+        }
+    };
+    Ok(path)
+}
+
+pub(crate) async fn export<B: JournalBackend>(
+    mut journal: B,
+    username: &str,
+    format: Format,
+    dir: &Path,
+) -> anyhow::Result<()> {
+    journal.check_can_access().await?;
+    let days = journal.all_days().await?;
+    let path = write(&days, format, dir, username)?;
+    log::info!("Exported {} days to {}", days.len(), path.display());
+    Ok(())
+}
@@ -0,0 +1,155 @@
+//! Bold, links, code and the like from Telegram messages, kept so the journal can show them.
+
+use serde::{Deserialize, Serialize};
+use teloxide::types::{MessageEntity, MessageEntityKind, MessageEntityRef};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Style {
+    Bold,
+    Italic,
+    Underline,
+    Strikethrough,
+    Spoiler,
+    Code,
+    /// Its own block, never mixed with other styles.
+    CodeBlock {
+        language: Option<String>,
+    },
+    Link(String),
+}
+
+/// A style for part of a text, in bytes, unlike Telegram's UTF-16 offsets.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Formatting {
+    pub start: usize,
+    pub end: usize,
+    pub style: Style,
+}
+
+/// Part of a text with the same styles throughout.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Span<'a> {
+    pub text: &'a str,
+    pub styles: Vec<&'a Style>,
+}
+
+/// What a text becomes, in order.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Part<'a> {
+    Paragraph(Vec<Span<'a>>),
+    CodeBlock {
+        code: &'a str,
+        language: Option<&'a str>,
+    },
+}
+
+fn style_of(entity: &MessageEntityRef) -> Option<Style> {
+    Some(match entity.kind() {
+        MessageEntityKind::Bold => Style::Bold,
+        MessageEntityKind::Italic => Style::Italic,
+        MessageEntityKind::Underline => Style::Underline,
+        MessageEntityKind::Strikethrough => Style::Strikethrough,
+        MessageEntityKind::Spoiler => Style::Spoiler,
+        MessageEntityKind::Code if entity.text().contains('\n') => {
+            Style::CodeBlock { language: None }
+        }
+        MessageEntityKind::Code => Style::Code,
+        MessageEntityKind::Pre { language } => Style::CodeBlock {
+            language: language.clone(),
+        },
+        MessageEntityKind::TextLink { url } => Style::Link(url.to_string()),
+        MessageEntityKind::Url if entity.text().contains("://") => {
+            Style::Link(entity.text().to_string())
+        }
+        MessageEntityKind::Url => Style::Link(format!("https://{}", entity.text())),
+        MessageEntityKind::Email => Style::Link(format!("mailto:{}", entity.text())),
+        MessageEntityKind::Mention => Style::Link(format!(
+            "https://t.me/{}",
+            entity.text().trim_start_matches('@')
+        )),
+        MessageEntityKind::TextMention { user } => {
+            Style::Link(format!("https://t.me/{}", user.username.as_ref()?))
+        }
+        _ => return None,
+    })
+}
+
+impl Formatting {
+    /// Only what the journal can show, the rest is kept as plain text.
+    pub fn from_entities(text: &str, entities: &[MessageEntity]) -> Vec<Self> {
+        MessageEntityRef::parse(text, entities)
+            .iter()
+            .filter_map(|entity| {
+                Some(Self {
+                    start: entity.start(),
+                    end: entity.end(),
+                    style: style_of(entity)?,
+                })
+            })
+            .collect()
+    }
+}
+
+fn spans<'a>(
+    text: &'a str,
+    start: usize,
+    end: usize,
+    formatting: &'a [Formatting],
+) -> Vec<Span<'a>> {
+    let mut bounds: Vec<usize> = formatting
+        .iter()
+        .flat_map(|f| [f.start, f.end])
+        .filter(|b| (start..end).contains(b))
+        .chain([start, end])
+        .collect();
+    bounds.sort_unstable();
+    bounds.dedup();
+    bounds
+        .windows(2)
+        .map(|w| Span {
+            text: &text[w[0]..w[1]],
+            styles: formatting
+                .iter()
+                .filter(|f| f.start <= w[0] && w[1] <= f.end)
+                .map(|f| &f.style)
+                .collect(),
+        })
+        .collect()
+}
+
+/// Splits the text around its code blocks. Always starts with a paragraph, even if empty.
+pub fn split<'a>(text: &'a str, formatting: &'a [Formatting]) -> Vec<Part<'a>> {
+    let mut blocks: Vec<_> = formatting
+        .iter()
+        .filter_map(|f| match &f.style {
+            Style::CodeBlock { language } => Some((f.start, f.end, language.as_deref())),
+            _ => None,
+        })
+        .collect();
+    blocks.sort_unstable_by_key(|(start, ..)| *start);
+    let mut parts = vec![];
+    let paragraph = |start: usize, end: usize, parts: &mut Vec<Part<'a>>| {
+        // The line breaks around code blocks are only there because they are blocks.
+        let slice = &text[start..end];
+        let start = start + slice.len() - slice.trim_start_matches('\n').len();
+        let end = start + slice.trim_matches('\n').len();
+        if parts.is_empty() || start < end {
+            parts.push(Part::Paragraph(spans(text, start, end, formatting)));
+        }
+    };
+    let mut done = 0;
+    for (start, end, language) in blocks {
+        // Telegram doesn't nest them, but better safe than panicking.
+        if start < done {
+            continue;
+        }
+        paragraph(done, start, &mut parts);
+        parts.push(Part::CodeBlock {
+            code: &text[start..end],
+            language,
+        });
+        done = end;
+    }
+    paragraph(done, text.len(), &mut parts);
+    parts
+}
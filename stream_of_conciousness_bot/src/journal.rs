@@ -0,0 +1,342 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use anyhow::Context;
+use chrono::{NaiveDate, NaiveTime};
+use serde::{Deserialize, Serialize};
+use teloxide::types::User;
+use unidecode::unidecode;
+
+use crate::{
+    attachment::File,
+    config::{Config, UserConfig},
+    formatting::Formatting,
+    ledger::Ledger,
+    messages::Messages,
+    outbox::Outbox,
+    reminders::Reminders,
+    search::{Hit, Search},
+    settings::{Settings, state_dir},
+    undo::UndoLog,
+};
+
+/// Where the journal of a single user is stored.
+// We never send these futures to other threads, so no need for Send bounds.
+#[allow(async_fn_in_trait)]
+pub trait JournalBackend {
+    /// Fail fast if the storage is not reachable. Also loads the known people.
+    async fn check_can_access(&mut self) -> anyhow::Result<()>;
+    /// Returns an id for the entry of that day, creating it if needed.
+    async fn get_or_create_day(&mut self, date: NaiveDate) -> anyhow::Result<String>;
+    /// Returns the mood it had before, if any.
+    async fn set_mood(&mut self, mood: u8, date: NaiveDate) -> anyhow::Result<Option<u8>>;
+    /// Back to a previous mood, even none.
+    async fn restore_mood(&mut self, mood: Option<u8>, date: NaiveDate) -> anyhow::Result<()>;
+    /// Backends that can tell skip entries with a marker already in that day. Returns the ids
+    /// of what the others became, by marker.
+    async fn add_text(
+        &mut self,
+        all_text: &[TextEntry],
+        date: NaiveDate,
+    ) -> anyhow::Result<EntryIds>;
+    /// Replaces an entry added before as `ids`, returning the new ids.
+    async fn edit_text(
+        &mut self,
+        entry: &TextEntry,
+        ids: &[String],
+        date: NaiveDate,
+    ) -> anyhow::Result<Vec<String>>;
+    /// Only adds the people that can be found, see [`AddedPeople::resolve`].
+    async fn add_people(
+        &mut self,
+        people: &[String],
+        aliases: &Aliases,
+        date: NaiveDate,
+    ) -> anyhow::Result<AddedPeople>;
+    /// Matched the same way as [`Self::add_people`], returns who was removed.
+    async fn remove_people(
+        &mut self,
+        people: &[String],
+        aliases: &Aliases,
+        date: NaiveDate,
+    ) -> anyhow::Result<Vec<String>>;
+    /// Adds someone not known yet, and remembers them for next time.
+    async fn add_new_person(&mut self, name: &str, date: NaiveDate) -> anyhow::Result<()>;
+    /// Returns the id of what the file became.
+    async fn add_file(&mut self, file: &File, date: NaiveDate) -> anyhow::Result<String>;
+    /// Takes back texts or files, by the ids they got when added.
+    async fn remove_blocks(&mut self, ids: &[String], date: NaiveDate) -> anyhow::Result<()>;
+    /// What's there, without creating anything. `None` if the day has no entry.
+    async fn read_day(&mut self, date: NaiveDate) -> anyhow::Result<Option<Day>>;
+    /// Newest first, at most [`crate::search::MAX_HITS`].
+    async fn search(&mut self, search: &Search) -> anyhow::Result<Vec<Hit>>;
+    /// Of the days between both, included, that have one. Oldest first.
+    async fn moods(
+        &mut self,
+        first: NaiveDate,
+        last: NaiveDate,
+    ) -> anyhow::Result<Vec<(NaiveDate, u8)>>;
+    /// Every day with an entry, oldest first. Reads the whole journal, so only for exports.
+    async fn all_days(&mut self) -> anyhow::Result<Vec<Day>>;
+    /// Drops what was read of the day, after writing to it failed.
+    fn forget_day(&mut self, _date: NaiveDate) {}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextEntry {
+    pub text: String,
+    /// Note that it might actually be from the next day.
+    pub time: NaiveTime,
+    /// Unique to the message it came from, so replaying it doesn't add it twice.
+    pub marker: Option<String>,
+    /// Only shown by backends that can, the text is fine without it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub formatting: Vec<Formatting>,
+}
+
+impl TextEntry {
+    pub fn new(text: String, time: NaiveTime) -> Self {
+        Self {
+            text,
+            time,
+            marker: None,
+            formatting: vec![],
+        }
+    }
+}
+
+/// A day as it is in the journal, to show it back to the user or export it.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Day {
+    pub date: NaiveDate,
+    pub mood: Option<u8>,
+    pub people: Vec<String>,
+    pub tags: Vec<String>,
+    /// Texts start with their time, as written in the journal.
+    pub lines: Vec<String>,
+}
+
+impl std::fmt::Display for Day {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "📅 {}", self.date.format("%A, %Y-%m-%d"))?;
+        if let Some(mood) = self.mood {
+            writeln!(f, "Mood: {}", mood)?;
+        }
+        if !self.people.is_empty() {
+            writeln!(f, "People: {}", self.people.join(", "))?;
+        }
+        if !self.tags.is_empty() {
+            writeln!(f, "Tags: {}", self.tags.join(", "))?;
+        }
+        if self.lines.is_empty() {
+            return writeln!(f, "\nNothing written yet.");
+        }
+        writeln!(f)?;
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Ids of what each entry became, by marker. Only for entries with one.
+pub type EntryIds = BTreeMap<String, Vec<String>>;
+
+/// Nicknames to the name of the person, keys are normalized with [`normalize_name`].
+pub type Aliases = BTreeMap<String, String>;
+
+#[derive(Default, Debug)]
+pub struct AddedPeople {
+    pub added: Vec<String>,
+    /// Of `added`, the ones that weren't in the day yet. Filled by backends, for undoing it.
+    pub new: Vec<String>,
+    pub not_found: Vec<String>,
+    /// With the candidates for each name.
+    pub ambiguous: Vec<(String, Vec<String>)>,
+}
+
+impl AddedPeople {
+    /// Matches the names against the known people. Only `added` should be written.
+    pub fn resolve(known: &BTreeSet<String>, aliases: &Aliases, names: &[String]) -> Self {
+        let mut result = Self::default();
+        for name in names {
+            match find_person(known, aliases, name) {
+                PersonMatch::Found(person) => {
+                    if !result.added.iter().any(|p| p == person) {
+                        result.added.push(person.to_string());
+                    }
+                }
+                PersonMatch::NotFound => result.not_found.push(name.clone()),
+                PersonMatch::Ambiguous(candidates) => result.ambiguous.push((
+                    name.clone(),
+                    candidates.into_iter().map(ToString::to_string).collect(),
+                )),
+            }
+        }
+        result
+    }
+
+    pub fn extend(&mut self, other: Self) {
+        for person in other.added {
+            if !self.added.contains(&person) {
+                self.added.push(person);
+            }
+        }
+        for person in other.new {
+            if !self.new.contains(&person) {
+                self.new.push(person);
+            }
+        }
+        self.not_found.extend(other.not_found);
+        self.ambiguous.extend(other.ambiguous);
+    }
+}
+
+/// All configured users, each with its own backend.
+pub struct Journals<B> {
+    per_username: BTreeMap<String, B>,
+    telegram_ids: BTreeMap<u64, String>,
+    pub settings: Settings,
+    pub outbox: Outbox,
+    pub ledger: Ledger,
+    pub messages: Messages,
+    pub undo: UndoLog,
+    pub reminders: Reminders,
+}
+
+impl<B> Journals<B> {
+    /// With the state in STATE_DIR.
+    pub fn from_config(
+        config: &Config,
+        backend: impl FnMut(&UserConfig) -> anyhow::Result<B>,
+    ) -> anyhow::Result<Self> {
+        Self::open(config, &state_dir(), backend)
+    }
+
+    pub fn open(
+        config: &Config,
+        state_dir: &Path,
+        mut backend: impl FnMut(&UserConfig) -> anyhow::Result<B>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            per_username: config
+                .users
+                .iter()
+                .map(|user| {
+                    let journal = backend(user)
+                        .with_context(|| format!("Failed to set up user {}", user.username))?;
+                    Ok((user.username.clone(), journal))
+                })
+                .collect::<anyhow::Result<_>>()?,
+            telegram_ids: config
+                .users
+                .iter()
+                .filter_map(|user| Some((user.telegram_id?, user.username.clone())))
+                .collect(),
+            settings: Settings::load(config, state_dir)?,
+            outbox: Outbox::load(state_dir)?,
+            ledger: Ledger::load(state_dir)?,
+            messages: Messages::load(state_dir)?,
+            undo: UndoLog::load(state_dir)?,
+            reminders: Reminders::load(state_dir)?,
+        })
+    }
+}
+
+impl<B: JournalBackend> Journals<B> {
+    pub fn user(&mut self, username: &str) -> anyhow::Result<&mut B> {
+        self.per_username.get_mut(username).context("Unknown user")
+    }
+
+    pub fn user_is_known(&self, username: &str) -> bool {
+        self.per_username.contains_key(username)
+    }
+
+    pub fn usernames(&self) -> Vec<String> {
+        self.per_username.keys().cloned().collect()
+    }
+
+    pub fn telegram_id(&self, username: &str) -> Option<u64> {
+        self.telegram_ids
+            .iter()
+            .find_map(|(id, u)| (u == username).then_some(*id))
+    }
+
+    /// Users with a configured telegram id are only recognized by it.
+    pub fn username_of(&self, user: &User) -> Option<String> {
+        if let Some(username) = self.telegram_ids.get(&user.id.0) {
+            return Some(username.clone());
+        }
+        let username = user.username.as_ref()?;
+        (self.user_is_known(username) && !self.telegram_ids.values().any(|u| u == username))
+            .then(|| username.clone())
+    }
+
+    pub async fn check_can_access(&mut self, usernames: BTreeSet<&str>) -> anyhow::Result<()> {
+        for username in usernames {
+            self.user(username)?.check_can_access().await?;
+        }
+        Ok(())
+    }
+}
+
+pub enum PersonMatch<'a> {
+    Found(&'a str),
+    NotFound,
+    /// Multiple people are equally close.
+    Ambiguous(Vec<&'a str>),
+}
+
+/// Ignores accents and case.
+pub fn normalize(text: &str) -> String {
+    unidecode(text).to_ascii_lowercase()
+}
+
+pub fn normalize_name(name: &str) -> String {
+    normalize(name.trim())
+}
+
+/// Finds the closest known person, ignoring accents and case. Aliases are checked first.
+pub fn find_person<'a>(
+    people: &'a BTreeSet<String>,
+    aliases: &Aliases,
+    name: &str,
+) -> PersonMatch<'a> {
+    if let Some(name) = people.get(name) {
+        return PersonMatch::Found(name);
+    }
+    let name = normalize_name(name);
+    if let Some(person) = aliases.get(&name) {
+        // The alias might not be exact either.
+        return find_person(people, &Aliases::new(), person);
+    }
+    let (mut min_dist, mut closest) = (usize::MAX, vec![]);
+    for person in people {
+        let lower_person = normalize_name(person);
+        let dist = lower_person
+            .split_whitespace()
+            .chain(std::iter::once(lower_person.as_str()))
+            .map(|p| edit_distance::edit_distance(name.as_str(), p))
+            .min()
+            .unwrap_or(usize::MAX);
+        if dist < min_dist && (dist == 0 || dist < 4.min(name.len().saturating_sub(3))) {
+            min_dist = dist;
+            closest = vec![person.as_str()];
+        } else if dist == min_dist {
+            closest.push(person.as_str());
+        }
+    }
+    match closest.len() {
+        0 => {
+            log::warn!("Didn't find person: {}", name);
+            PersonMatch::NotFound
+        }
+        1 => PersonMatch::Found(closest[0]),
+        _ => {
+            log::warn!("Ambiguous person: {}, could be {:?}", name, closest);
+            PersonMatch::Ambiguous(closest)
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! Telegram update ids already applied, per user. Updates are only acked on the next
+//! `get_updates`, so after a crash the same updates come again, and these are skipped.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use crate::state_file;
+
+/// Ids only increase, and Telegram forgets updates after a day, so old ones are never needed.
+const KEEP_PER_USER: usize = 1000;
+
+pub struct Ledger {
+    path: PathBuf,
+    applied: BTreeMap<String, BTreeSet<u32>>,
+}
+
+impl Ledger {
+    /// From `ledger.json` in the state directory.
+    pub fn load(state_dir: &Path) -> anyhow::Result<Self> {
+        Self::open(state_dir.join("ledger.json"))
+    }
+
+    pub fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let applied = state_file::read(&path)?;
+        Ok(Self { path, applied })
+    }
+
+    pub fn is_applied(&self, username: &str, update_id: u32) -> bool {
+        self.applied
+            .get(username)
+            .is_some_and(|ids| ids.contains(&update_id))
+    }
+
+    /// Saves right away, the whole point is surviving crashes.
+    pub fn record(
+        &mut self,
+        username: &str,
+        update_ids: impl IntoIterator<Item = u32>,
+    ) -> anyhow::Result<()> {
+        let ids = self.applied.entry(username.to_string()).or_default();
+        ids.extend(update_ids);
+        while ids.len() > KEEP_PER_USER {
+            ids.pop_first();
+        }
+        state_file::write(&self.path, &self.applied)
+    }
+
+    /// Logs instead of failing, as the command itself worked.
+    pub fn record_or_log(&mut self, username: &str, update_ids: impl IntoIterator<Item = u32>) {
+        if let Err(e) = self.record(username, update_ids) {
+            log::error!("Failed to record applied updates: {:?}", e);
+        }
+    }
+}
@@ -1,100 +1,325 @@
+use std::path::Path;
+
+use anyhow::Context;
+
 use teloxide::{
     prelude::*,
     requests::HasPayload,
-    types::{AllowedUpdate, MediaKind, MediaText, MessageCommon, MessageKind, UpdateKind, User},
+    types::{AllowedUpdate, UpdateKind, User},
     utils::command::BotCommands,
 };
 
-mod commands;
-mod notion_manager;
+pub mod attachment;
+pub mod commands;
+pub mod config;
+pub mod daemon;
+pub mod disambiguation;
+pub mod export;
+pub mod formatting;
+pub mod journal;
+pub mod ledger;
+pub mod local_journal;
+pub mod messages;
+pub mod notion_manager;
+pub mod outbox;
+pub mod reminders;
+pub mod reply;
+pub mod report;
+pub mod retry;
+pub mod search;
+pub mod settings;
+pub mod state_file;
+pub mod stats;
+pub mod undo;
+pub mod webhook;
 
-use commands::Command;
+use attachment::Attachment;
+use commands::{Command, Content, Handled, IncomingMessage};
+use formatting::Formatting;
+use journal::{JournalBackend, Journals};
+use local_journal::LocalJournal;
+use notion_manager::{NotionManager, NotionManagerForUser};
+use report::PollReport;
 
-// TODO: Split polling from updating
-pub async fn poll_once_and_update_notion() -> anyhow::Result<()> {
-    log::info!("Polling all pending messages from bot...");
+/// Handles the pending messages, then exits. Failed commands are in the report, only not
+/// being able to run at all is an error.
+pub async fn poll_once_and_update_notion() -> anyhow::Result<PollReport> {
+    run(Mode::Once).await
+}
 
-    let mut notion = notion_manager::NotionManager::new().await?;
-    let bot = Bot::new(std::env::var("TELEGRAM_TOKEN")?);
+/// A single update that Telegram sent to the webhook, see [`webhook`] for checking the request
+/// first. Like polling, failed commands are in the report.
+pub async fn handle_webhook_update(update: Update) -> anyhow::Result<PollReport> {
+    run(Mode::Webhook(Box::new(update))).await
+}
+
+/// Writes the whole journal of a user to files in `dir`, see [`export::write`]. Doesn't need
+/// Telegram or any of the bot's state, only the user's journal.
+pub async fn export(username: &str, format: export::Format, dir: &Path) -> anyhow::Result<()> {
+    let config = config::Config::load()?;
+    let user = config
+        .users
+        .iter()
+        .find(|user| user.username == username)
+        .with_context(|| format!("Unknown user {}", username))?;
+    match Backend::from_env()? {
+        Backend::Local => {
+            export::export(LocalJournal::for_user(user)?, username, format, dir).await
+        }
+        Backend::Notion => {
+            let journal = NotionManagerForUser::from_env()?(user)?;
+            export::export(journal, username, format, dir).await
+        }
+    }
+}
+
+/// Keeps handling messages as they arrive, until SIGTERM or Ctrl-C.
+pub async fn run_daemon() -> anyhow::Result<()> {
+    run(Mode::Daemon).await.map(|_| ())
+}
+
+/// Where the journals are, from JOURNAL_BACKEND.
+enum Backend {
+    /// The default.
+    Notion,
+    Local,
+}
 
+impl Backend {
+    fn from_env() -> anyhow::Result<Self> {
+        match std::env::var("JOURNAL_BACKEND").as_deref() {
+            Ok("local") => Ok(Self::Local),
+            Ok("notion") | Err(_) => Ok(Self::Notion),
+            Ok(other) => anyhow::bail!("Unknown JOURNAL_BACKEND: {}", other),
+        }
+    }
+}
+
+enum Mode {
+    Once,
+    Daemon,
+    Webhook(Box<Update>),
+}
+
+/// Uses the backend from JOURNAL_BACKEND, either "notion" (default) or "local". The daemon
+/// logs as it goes instead of reporting.
+async fn run(mode: Mode) -> anyhow::Result<PollReport> {
+    settings::warn_if_state_dir_not_writable();
+    let config = config::Config::load()?;
+    match Backend::from_env()? {
+        Backend::Local => run_with(mode, Journals::new_local(&config)?).await,
+        Backend::Notion => run_with(mode, NotionManager::new(&config)?).await,
+    }
+}
+
+async fn run_with<B: JournalBackend>(
+    mode: Mode,
+    journals: Journals<B>,
+) -> anyhow::Result<PollReport> {
+    let bot = Bot::new(std::env::var("TELEGRAM_TOKEN")?);
     if std::env::var("SET_COMMANDS").is_ok() {
         log::info!("Setting commands using API");
         bot.set_my_commands(Command::bot_commands()).send().await?;
     }
+    match mode {
+        Mode::Once => poll_once(&bot, journals).await,
+        Mode::Daemon => daemon::run(&bot, journals)
+            .await
+            .map(|()| PollReport::default()),
+        Mode::Webhook(update) => webhook::handle(&bot, journals, *update).await,
+    }
+}
 
-    let mut any_update = false;
-    let mut some_failures = false;
-
-    let mut off = None;
+/// With a `timeout` (in seconds), waits for updates if there are none.
+/// Updates before `offset` are acked and never returned again.
+pub(crate) async fn get_updates(
+    bot: &Bot,
+    offset: Option<i32>,
+    timeout: u32,
+) -> anyhow::Result<Vec<Update>> {
+    Ok(bot
+        .get_updates()
+        .with_payload_mut(|p| {
+            p.offset = offset;
+            p.timeout = Some(timeout);
+            p.allowed_updates = Some(vec![
+                AllowedUpdate::Message,
+                AllowedUpdate::EditedMessage,
+                AllowedUpdate::CallbackQuery,
+            ]);
+        })
+        .send()
+        .await?)
+}
 
-    let all_failure = loop {
-        let updates = bot
-            .get_updates()
-            .with_payload_mut(|p| {
-                p.offset = off;
-                p.allowed_updates = Some(vec![AllowedUpdate::Message]);
-            })
-            .send()
-            .await?;
+/// Texts become commands, and files attachments. Edits of files can only change the caption.
+fn incoming_message<B: JournalBackend>(
+    journals: &Journals<B>,
+    update_id: u32,
+    msg: Message,
+    edited: bool,
+    report: &mut PollReport,
+) -> Option<IncomingMessage> {
+    let username = known_user(journals, msg.from.as_ref()?, report)?;
+    let (content, formatting) = if let Some(text) = msg.text() {
+        (
+            Content::Command(Command::parse_or_text(text.to_string())),
+            Formatting::from_entities(text, msg.entities().unwrap_or_default()),
+        )
+    } else {
+        let caption = msg.caption();
+        let formatting = Formatting::from_entities(
+            caption.unwrap_or_default(),
+            msg.caption_entities().unwrap_or_default(),
+        );
+        if edited {
+            (
+                Content::Command(Command::Text(caption?.to_string())),
+                formatting,
+            )
+        } else if let Some(attachment) = Attachment::from_message(&msg) {
+            (Content::Attachment(attachment), formatting)
+        } else {
+            log::info!("Not a text or file message: {:?}", msg);
+            report.user(&username).skipped += 1;
+            return None;
+        }
+    };
+    Some(IncomingMessage {
+        content,
+        username,
+        date: msg.date,
+        chat_id: msg.chat.id,
+        day: None,
+        update_id,
+        message_id: Some(msg.id),
+        formatting,
+        edited,
+    })
+}
 
-        off = updates.last().map(|u| u.id.as_offset());
+/// Counts the update for the user, or as from an unknown one.
+fn known_user<B: JournalBackend>(
+    journals: &Journals<B>,
+    user: &User,
+    report: &mut PollReport,
+) -> Option<String> {
+    let Some(username) = journals.username_of(user) else {
+        report.unknown_user(user.username.clone().unwrap_or_else(|| user.id.to_string()));
+        return None;
+    };
+    report.user(&username).updates += 1;
+    Some(username)
+}
 
-        let cmds: Vec<_> = updates
-            .into_iter()
-            .flat_map(|update| {
-                if let UpdateKind::Message(Message {
-                    date,
-                    kind:
-                        MessageKind::Common(MessageCommon {
-                            media_kind: MediaKind::Text(MediaText { text, .. }),
-                            ..
-                        }),
-                    from:
-                        Some(User {
-                            username: Some(username),
-                            ..
-                        }),
-                    ..
-                }) = update.kind
-                {
-                    notion
-                        .user_is_known(&username)
-                        .then(|| (Command::parse_or_text(text), username, date))
+/// Writes the updates to the journals, reporting what happened. If some commands succeeded,
+/// the updates will be acked, so the failed ones go to the outbox. If none did, they aren't
+/// acked and Telegram sends them again.
+pub(crate) async fn handle_updates<B: JournalBackend>(
+    bot: &Bot,
+    journals: &mut Journals<B>,
+    updates: Vec<Update>,
+) -> anyhow::Result<PollReport> {
+    let mut report = PollReport::default();
+    let mut cmds = vec![];
+    for update in updates {
+        match update.kind {
+            UpdateKind::Message(msg) => cmds.extend(incoming_message(
+                journals,
+                update.id.0,
+                msg,
+                false,
+                &mut report,
+            )),
+            UpdateKind::EditedMessage(msg) => cmds.extend(incoming_message(
+                journals,
+                update.id.0,
+                msg,
+                true,
+                &mut report,
+            )),
+            UpdateKind::CallbackQuery(query) => {
+                let Some(username) = known_user(journals, &query.from, &mut report) else {
+                    continue;
+                };
+                let msg = if reminders::is_mood_callback(&query) {
+                    reminders::handle_callback(bot, query, username, update.id.0).await
                 } else {
-                    log::info!("Not a text message: {:?}", update);
-                    None
+                    disambiguation::handle_callback(bot, query, username, update.id.0).await
+                };
+                match msg {
+                    Ok(msg) => cmds.push(msg),
+                    Err(e) => log::error!("Failed to handle callback: {:?}", e),
                 }
-            })
+            }
+            _ => log::info!("Not a message: {:?}", update),
+        }
+    }
+    if cmds.is_empty() {
+        return Ok(report);
+    }
+    // Let's fail fast if we can't talk to Notion at all.
+    journals
+        .check_can_access(cmds.iter().map(|msg| msg.username.as_str()).collect())
+        .await?;
+    let Handled {
+        report: handled,
+        failed,
+    } = Command::handle(bot, cmds, journals).await?;
+    report.merge(handled);
+    if report.successes() > 0 && !failed.is_empty() {
+        log::info!("Saving {} failed commands to the outbox", failed.len());
+        let applied: Vec<_> = failed
+            .iter()
+            .map(|(cmd, _)| (cmd.username.clone(), cmd.update_ids.clone()))
             .collect();
-
-        if !any_update && !cmds.is_empty() {
-            // Let's fail fast if we can't talk to Notion at all.
-            notion
-                .check_can_access_database(
-                    cmds.iter()
-                        .map(|(_, username, _)| username.as_str())
-                        .collect(),
-                )
-                .await?;
-            any_update = true;
+        journals.outbox.add_or_log(failed, chrono::Utc::now());
+        // The outbox takes care of them now.
+        for (username, update_ids) in applied {
+            journals.ledger.record_or_log(&username, update_ids);
         }
-        let (suc, tot) = Command::handle(&bot, cmds, &mut notion).await?;
-        some_failures = some_failures || suc < tot;
+    }
+    Ok(report)
+}
+
+async fn poll_once<B: JournalBackend>(
+    bot: &Bot,
+    mut journals: Journals<B>,
+) -> anyhow::Result<PollReport> {
+    log::info!("Polling all pending messages from bot...");
+
+    let mut report = PollReport::default();
+
+    let mut off = None;
+
+    if let Err(e) = outbox::retry_due(bot, &mut journals).await {
+        log::error!("Failed to retry the outbox: {:?}", e);
+    }
+    reminders::send_due(bot, &mut journals).await;
+
+    loop {
+        let updates = get_updates(bot, off, 0).await?;
+
+        off = updates.last().map(|u| u.id.as_offset());
+
+        let batch = handle_updates(bot, &mut journals, updates).await?;
+        let all_failed = batch.none_succeeded();
+        report.merge(batch);
         // No updates
         if off.is_none() {
-            break false;
+            break;
         // If everything failed, Notion is likely down, let's not ack the messages
-        // and hope it works later.
-        } else if suc == 0 {
-            break true;
+        // and hope it works later. Without any commands (like only stickers or edits
+        // ignored), there's nothing to retry.
+        } else if all_failed {
+            log::error!("All messages failed, will try again later.");
+            report.all_failed = true;
+            break;
         }
-    };
+    }
 
-    if all_failure {
-        anyhow::bail!("All messages failed, will try again later.")
-    } else if some_failures {
-        anyhow::bail!("Some errors occurred, but some successes, continuing.")
-    } else {
-        Ok(())
+    if report.has_failures() && !report.all_failed {
+        log::warn!("Some errors occurred, but some successes, continuing.");
     }
+    Ok(report)
 }
@@ -0,0 +1,348 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use chrono::{NaiveDate, NaiveTime};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    attachment::File,
+    config::{Config, UserConfig},
+    journal::{AddedPeople, Aliases, Day, EntryIds, JournalBackend, Journals, TextEntry},
+    search::{Hit, MAX_HITS, Search},
+    state_file,
+};
+
+/// Everything is in a single JSON file per user. Good for running without a Notion account.
+pub struct LocalJournal {
+    path: PathBuf,
+    data: LocalJournalData,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct LocalJournalData {
+    /// Known people, edit the file to add more.
+    people: BTreeSet<String>,
+    days: BTreeMap<NaiveDate, LocalDay>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct LocalDay {
+    mood: Option<u8>,
+    people: BTreeSet<String>,
+    #[serde(deserialize_with = "texts_with_ids")]
+    texts: Vec<LocalText>,
+    /// Ids are never reused, so an old one can't point to another text.
+    #[serde(default)]
+    next_id: u64,
+    /// Of the texts, see [`TextEntry::marker`].
+    #[serde(default)]
+    markers: BTreeSet<String>,
+    /// Relative to the journal file, see [`LocalJournal::add_file`].
+    #[serde(default)]
+    files: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LocalText {
+    /// Unlike the index, it doesn't change when others are removed.
+    id: u64,
+    text: String,
+    time: NaiveTime,
+}
+
+/// Journals written before texts had ids have `[text, time]` pairs, their id is the index.
+fn texts_with_ids<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<LocalText>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        WithId(LocalText),
+        Pair(String, NaiveTime),
+    }
+    Ok(Vec::<Stored>::deserialize(deserializer)?
+        .into_iter()
+        .zip(0..)
+        .map(|(stored, index)| match stored {
+            Stored::WithId(text) => text,
+            Stored::Pair(text, time) => LocalText {
+                id: index,
+                text,
+                time,
+            },
+        })
+        .collect())
+}
+
+impl LocalDay {
+    fn new_id(&mut self) -> u64 {
+        let after_texts = self.texts.iter().map(|text| text.id + 1).max().unwrap_or(0);
+        let id = self.next_id.max(after_texts);
+        self.next_id = id + 1;
+        id
+    }
+
+    fn text(&self, id: &str) -> anyhow::Result<usize> {
+        let id: u64 = id.parse().context("Unknown id")?;
+        self.texts
+            .iter()
+            .position(|text| text.id == id)
+            .context("Text not found")
+    }
+
+    /// Files go after the texts, as their time isn't kept.
+    fn lines(&self) -> Vec<String> {
+        let texts = self
+            .texts
+            .iter()
+            .map(|text| format!("[{}] {}", text.time.format("%H:%M"), text.text));
+        let files = self.files.iter().map(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            format!("📎 {}", name)
+        });
+        texts.chain(files).collect()
+    }
+
+    fn to_day(&self, date: NaiveDate) -> Day {
+        Day {
+            date,
+            mood: self.mood,
+            people: self.people.iter().cloned().collect(),
+            tags: vec![],
+            lines: self.lines(),
+        }
+    }
+}
+
+impl LocalJournal {
+    pub fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let data = state_file::read(&path)?;
+        Ok(Self { path, data })
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        state_file::write(&self.path, &self.data)
+    }
+
+    fn day(&mut self, date: NaiveDate) -> &mut LocalDay {
+        self.data.days.entry(date).or_default()
+    }
+}
+
+impl JournalBackend for LocalJournal {
+    async fn check_can_access(&mut self) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        self.save()
+    }
+
+    async fn get_or_create_day(&mut self, date: NaiveDate) -> anyhow::Result<String> {
+        if !self.data.days.contains_key(&date) {
+            self.day(date);
+            self.save()?;
+        }
+        Ok(date.to_string())
+    }
+
+    async fn set_mood(&mut self, mood: u8, date: NaiveDate) -> anyhow::Result<Option<u8>> {
+        let previous = self.day(date).mood.replace(mood.clamp(0, 100));
+        self.save()?;
+        Ok(previous)
+    }
+
+    async fn restore_mood(&mut self, mood: Option<u8>, date: NaiveDate) -> anyhow::Result<()> {
+        self.day(date).mood = mood;
+        self.save()
+    }
+
+    async fn add_text(
+        &mut self,
+        all_text: &[TextEntry],
+        date: NaiveDate,
+    ) -> anyhow::Result<EntryIds> {
+        let day = self.day(date);
+        let mut ids = EntryIds::new();
+        for entry in all_text {
+            if let Some(marker) = &entry.marker
+                && !day.markers.insert(marker.clone())
+            {
+                log::info!("Skipping text already added: {:?}", entry);
+                continue;
+            }
+            let id = day.new_id();
+            if let Some(marker) = &entry.marker {
+                ids.insert(marker.clone(), vec![id.to_string()]);
+            }
+            day.texts.push(LocalText {
+                id,
+                text: entry.text.clone(),
+                time: entry.time,
+            });
+        }
+        self.save()?;
+        Ok(ids)
+    }
+
+    async fn edit_text(
+        &mut self,
+        entry: &TextEntry,
+        ids: &[String],
+        date: NaiveDate,
+    ) -> anyhow::Result<Vec<String>> {
+        let day = self.day(date);
+        let index = day.text(ids.first().context("No id")?)?;
+        let text = &mut day.texts[index];
+        text.text = entry.text.clone();
+        text.time = entry.time;
+        self.save()?;
+        Ok(ids.to_vec())
+    }
+
+    async fn add_people(
+        &mut self,
+        people: &[String],
+        aliases: &Aliases,
+        date: NaiveDate,
+    ) -> anyhow::Result<AddedPeople> {
+        let mut result = AddedPeople::resolve(&self.data.people, aliases, people);
+        let day = self.day(date);
+        result.new = result
+            .added
+            .iter()
+            .filter(|person| day.people.insert(person.to_string()))
+            .cloned()
+            .collect();
+        self.save()?;
+        Ok(result)
+    }
+
+    async fn remove_people(
+        &mut self,
+        people: &[String],
+        aliases: &Aliases,
+        date: NaiveDate,
+    ) -> anyhow::Result<Vec<String>> {
+        let removed = AddedPeople::resolve(&self.data.people, aliases, people).added;
+        let day = self.day(date);
+        let removed = removed
+            .into_iter()
+            .filter(|person| day.people.remove(person))
+            .collect();
+        self.save()?;
+        Ok(removed)
+    }
+
+    /// Saved as `{username}/{date}/{name}` next to the journal file, which is also the id.
+    async fn add_file(&mut self, file: &File, date: NaiveDate) -> anyhow::Result<String> {
+        let name = Path::new(&file.name)
+            .file_name()
+            .context("Invalid file name")?
+            .to_string_lossy();
+        let stem = self.path.file_stem().context("Invalid journal path")?;
+        let dir = PathBuf::from(stem).join(date.to_string());
+        let base = self.path.parent().unwrap_or(Path::new("."));
+        std::fs::create_dir_all(base.join(&dir))?;
+        // Don't overwrite files with the same name in the same day.
+        let relative = (0..)
+            .map(|i| match i {
+                0 => dir.join(&*name),
+                i => dir.join(format!("{}_{}", i, name)),
+            })
+            .find(|path| !base.join(path).exists())
+            .unwrap();
+        std::fs::write(base.join(&relative), &file.data)?;
+        let id = relative.to_string_lossy().to_string();
+        self.day(date).files.push(relative);
+        self.save()?;
+        Ok(id)
+    }
+
+    async fn remove_blocks(&mut self, ids: &[String], date: NaiveDate) -> anyhow::Result<()> {
+        let base = self.path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        let day = self.day(date);
+        for id in ids {
+            if let Some(index) = day.files.iter().position(|path| path == Path::new(id)) {
+                let path = day.files.remove(index);
+                if let Err(e) = std::fs::remove_file(base.join(&path)) {
+                    log::warn!("Failed to delete {}: {:?}", path.display(), e);
+                }
+            } else {
+                let index = day.text(id)?;
+                day.texts.remove(index);
+            }
+        }
+        self.save()
+    }
+
+    async fn read_day(&mut self, date: NaiveDate) -> anyhow::Result<Option<Day>> {
+        Ok(self.data.days.get(&date).map(|day| day.to_day(date)))
+    }
+
+    async fn all_days(&mut self) -> anyhow::Result<Vec<Day>> {
+        Ok(self
+            .data
+            .days
+            .iter()
+            .map(|(date, day)| day.to_day(*date))
+            .collect())
+    }
+
+    async fn search(&mut self, search: &Search) -> anyhow::Result<Vec<Hit>> {
+        let days = self
+            .data
+            .days
+            .iter()
+            .rev()
+            .take_while(|(date, _)| search.since.is_none_or(|since| **date >= since));
+        let hits = days.flat_map(|(date, day)| {
+            let lines = day.lines();
+            search
+                .find(&lines)
+                .map(|snippet| Hit {
+                    date: *date,
+                    snippet,
+                    url: None,
+                })
+                .collect::<Vec<_>>()
+        });
+        Ok(hits.take(MAX_HITS).collect())
+    }
+
+    async fn moods(
+        &mut self,
+        first: NaiveDate,
+        last: NaiveDate,
+    ) -> anyhow::Result<Vec<(NaiveDate, u8)>> {
+        Ok(self
+            .data
+            .days
+            .range(first..=last)
+            .filter_map(|(date, day)| Some((*date, day.mood?)))
+            .collect())
+    }
+
+    async fn add_new_person(&mut self, name: &str, date: NaiveDate) -> anyhow::Result<()> {
+        self.data.people.insert(name.to_string());
+        self.day(date).people.insert(name.to_string());
+        self.save()
+    }
+}
+
+impl LocalJournal {
+    /// The user's file in LOCAL_JOURNAL_DIR, named after the username.
+    pub fn for_user(user: &UserConfig) -> anyhow::Result<Self> {
+        let dir = PathBuf::from(
+            std::env::var("LOCAL_JOURNAL_DIR").unwrap_or_else(|_| "journal".to_string()),
+        );
+        Self::open(dir.join(format!("{}.json", user.username)))
+    }
+}
+
+impl Journals<LocalJournal> {
+    /// One file per user, see [`LocalJournal::for_user`].
+    pub fn new_local(config: &Config) -> anyhow::Result<Self> {
+        Self::from_config(config, LocalJournal::for_user)
+    }
+}
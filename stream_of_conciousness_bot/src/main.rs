@@ -1,7 +1,33 @@
 #[tokio::main]
 async fn main() {
     pretty_env_logger::init_timed();
-    stream_of_conciousness_bot::poll_once_and_update_notion()
-        .await
-        .unwrap();
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("daemon") => stream_of_conciousness_bot::run_daemon().await.unwrap(),
+        // export <username> [markdown|jsonl] [dir]
+        Some("export") => {
+            let username = args
+                .get(2)
+                .expect("Usage: export <username> [format] [dir]");
+            let format = args
+                .get(3)
+                .map_or("markdown", String::as_str)
+                .parse()
+                .unwrap();
+            let dir = std::path::Path::new(args.get(4).map_or("export", String::as_str));
+            stream_of_conciousness_bot::export(username, format, dir)
+                .await
+                .unwrap();
+        }
+        _ => {
+            let report = stream_of_conciousness_bot::poll_once_and_update_notion()
+                .await
+                .unwrap();
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+            // So whatever runs it on a schedule notices. Partial failures are in the outbox.
+            if report.all_failed {
+                std::process::exit(1);
+            }
+        }
+    }
 }
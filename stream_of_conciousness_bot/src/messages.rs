@@ -0,0 +1,138 @@
+//! What each Telegram message became in the journal, so edits to it can be applied there too.
+//! Keyed by the marker of the message, see [`crate::journal::TextEntry::marker`].
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use chrono::{Days, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{journal::normalize_name, state_file};
+
+/// Older messages are forgotten, and their edits ignored.
+const KEEP_DAYS: u64 = 30;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Journaled {
+    /// The ids the backend gave to what the text became.
+    Text(Vec<String>),
+    Mood(u8),
+    /// As written, before matching them to people.
+    People(Vec<String>),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JournaledMessage {
+    pub date: NaiveDate,
+    pub content: Journaled,
+}
+
+pub struct Messages {
+    path: PathBuf,
+    per_user: BTreeMap<String, BTreeMap<String, JournaledMessage>>,
+}
+
+/// Markers end with the message id, which only increases in a chat.
+fn message_id(marker: &str) -> Option<i64> {
+    marker.rsplit_once('/')?.1.parse().ok()
+}
+
+impl Messages {
+    /// From `messages.json` in the state directory.
+    pub fn load(state_dir: &Path) -> anyhow::Result<Self> {
+        Self::open(state_dir.join("messages.json"))
+    }
+
+    pub fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let per_user = state_file::read(&path)?;
+        Ok(Self { path, per_user })
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        state_file::write(&self.path, &self.per_user)
+    }
+
+    pub fn get(&self, username: &str, marker: &str) -> Option<&JournaledMessage> {
+        self.per_user.get(username)?.get(marker)
+    }
+
+    /// Replaces what was there for the same message.
+    pub fn record(
+        &mut self,
+        username: &str,
+        marker: &str,
+        date: NaiveDate,
+        content: Journaled,
+    ) -> anyhow::Result<()> {
+        let messages = self.per_user.entry(username.to_string()).or_default();
+        messages.insert(marker.to_string(), JournaledMessage { date, content });
+        if let Some(oldest) = Utc::now()
+            .date_naive()
+            .checked_sub_days(Days::new(KEEP_DAYS))
+        {
+            messages.retain(|_, message| message.date >= oldest);
+        }
+        self.save()
+    }
+
+    /// Logs instead of failing, only editing the message later won't work.
+    pub fn record_or_log(
+        &mut self,
+        username: &str,
+        marker: &str,
+        date: NaiveDate,
+        content: Journaled,
+    ) {
+        if let Err(e) = self.record(username, marker, date, content) {
+            log::error!("Failed to record message {}: {:?}", marker, e);
+        }
+    }
+
+    /// When what it became is gone, like after `/undo`.
+    pub fn forget_or_log(&mut self, username: &str, marker: &str) {
+        if let Some(messages) = self.per_user.get_mut(username)
+            && messages.remove(marker).is_some()
+            && let Err(e) = self.save()
+        {
+            log::error!("Failed to forget message {}: {:?}", marker, e);
+        }
+    }
+
+    fn of_day(&self, username: &str, date: NaiveDate) -> impl Iterator<Item = (&str, &Journaled)> {
+        self.per_user
+            .get(username)
+            .into_iter()
+            .flatten()
+            .filter(move |(_, message)| message.date == date)
+            .map(|(marker, message)| (marker.as_str(), &message.content))
+    }
+
+    /// Of the last mood message of that day.
+    pub fn latest_mood(&self, username: &str, date: NaiveDate) -> Option<u8> {
+        self.of_day(username, date)
+            .filter_map(|(marker, content)| match content {
+                Journaled::Mood(mood) => Some((message_id(marker), *mood)),
+                _ => None,
+            })
+            .max_by_key(|(id, _)| *id)
+            .map(|(_, mood)| mood)
+    }
+
+    /// Whether another message of that day mentions the name.
+    pub fn mentioned_elsewhere(
+        &self,
+        username: &str,
+        date: NaiveDate,
+        except: &str,
+        name: &str,
+    ) -> bool {
+        let name = normalize_name(name);
+        self.of_day(username, date).any(|(marker, content)| {
+            marker != except
+                && matches!(content, Journaled::People(names)
+                    if names.iter().any(|n| normalize_name(n) == name))
+        })
+    }
+}
@@ -1,68 +1,142 @@
-use std::collections::{btree_map::Entry, BTreeMap, BTreeSet};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::{Duration, Instant},
+};
 
 use anyhow::Context;
 use chrono::{NaiveDate, NaiveTime};
 use maplit::btreemap;
 use notion_client::{
+    NotionClientError,
     endpoints::{
-        blocks::append::request::AppendBlockChildrenRequestBuilder,
-        databases::query::request::{
-            DateCondition, Filter, FilterType, MultiSelectCondition, PropertyCondition,
-            QueryDatabaseRequestBuilder,
+        blocks::{
+            append::{
+                request::AppendBlockChildrenRequestBuilder, response::AppendBlockChildrenResponse,
+            },
+            retrieve::response::RetrieveBlockChilerenResponse,
+        },
+        databases::{
+            query::request::{
+                DateCondition, Filter, FilterType, MultiSelectCondition, PropertyCondition,
+                QueryDatabaseRequestBuilder, Sort, SortDirection,
+            },
+            query::response::QueryDatabaseResponse,
+            update::request::{UpdateADatabaseRequest, UpdateADatabaseRequestBuilder},
         },
         pages::{
             create::request::CreateAPageRequestBuilder,
             update::request::UpdatePagePropertiesRequestBuilder,
         },
-        Client,
     },
     objects::{
-        block::{Block, BlockType, ParagraphValue},
-        database::DatabaseProperty,
+        Response,
+        block::{Block, BlockType, CodeValue, Language, ParagraphValue},
+        database::{
+            Database, DatabaseProperty, OptionValue,
+            SelectPropertyValue as DatabaseSelectPropertyValue,
+        },
         emoji::Emoji,
         page::{DateOrDateTime, DatePropertyValue, Icon, Page, PageProperty, SelectPropertyValue},
         parent::Parent,
-        rich_text::{RichText, Text},
+        rich_text::{Annotations, Link, RichText, Text, TextColor},
     },
 };
-use unidecode::unidecode;
+use reqwest::{
+    Method,
+    header::{AUTHORIZATION, CONTENT_TYPE, HeaderMap, HeaderValue, RETRY_AFTER},
+    multipart::{Form, Part},
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::json;
+
+use teloxide::types::ChatId;
+
+use crate::{
+    attachment::{AttachmentKind, File},
+    config::{Config, PropertyNames, UserConfig},
+    formatting::{self, Span, Style, split},
+    journal::{AddedPeople, Aliases, Day, EntryIds, JournalBackend, Journals, TextEntry},
+    messages::Journaled,
+    reply::Reply,
+    retry::{RequestBudget, Retrier, RetryAfter, RetryPolicy},
+    search::{self, Hit, MAX_DAYS, MAX_HITS, Search},
+    stats::{self, MoodStats, Period},
+    undo::{Applied, Undo},
+};
+
+/// Same as notion-client, which doesn't export them.
+const NOTION_URI: &str = "https://api.notion.com/v1";
+const NOTION_VERSION: &str = "2022-06-28";
+/// People can be added in Notion meanwhile, so when running for long they are read again.
+const PEOPLE_TTL: Duration = Duration::from_secs(10 * 60);
+
 #[derive(Clone)]
 struct DatabaseId(String);
 
 pub struct NotionManagerForUser {
-    api: Client,
-    initialized: bool,
+    http: reqwest::Client,
+    /// [`NOTION_URI`], unless another server is used, like a fake one in tests.
+    base_url: String,
+    /// When the database (and so the people) was last read.
+    loaded_at: Option<Instant>,
+    people_ttl: Duration,
     db_id: DatabaseId,
+    props: PropertyNames,
     page_cache: BTreeMap<NaiveDate, Page>,
     people: BTreeSet<String>,
+    retrier: Retrier,
 }
 
-pub struct NotionManager {
-    per_username: BTreeMap<String, NotionManagerForUser>,
-}
+pub type NotionManager = Journals<NotionManagerForUser>;
 
-const MOOD: &str = "Mood";
-const TAGS: &str = "Tags";
-const DATE: &str = "Date";
-const TITLE: &str = "title"; // Default and lowercase in notion
-const PEOPLE: &str = "Pessoas";
-const STREAM_OF_CONSCIOUSNESS: &str = "Stream of conciousness";
-/// The day actually changes at 6am.
-pub const HOUR_CUT_TO_NEXT_DAY: u32 = 6;
-
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum InnerCommand {
     Mood(u8),
-    /// Note that the NaiveTime might actually be from the next day.
-    Text(Vec<(String, NaiveTime)>),
+    Text(Vec<TextEntry>),
     People(Vec<String>),
+    AddPerson(String),
+    /// With the caption, if any.
+    File(File, Option<TextEntry>),
+    /// With the ids of what it was, see [`crate::messages`].
+    EditText(TextEntry, Vec<String>),
+    /// As written, people only in the old version of the message are removed.
+    EditPeople {
+        removed: Vec<String>,
+        added: Vec<String>,
+    },
+    /// Takes back the last write of the user, see [`crate::undo`].
+    Undo,
+    /// Sends the day back to the chat.
+    ShowDay,
+    Search(Search),
+    /// Of the period ending on the command's day.
+    Stats(Period),
+}
+
+impl InnerCommand {
+    /// Undos and reads only make sense right away, by a later retry they'd do something else.
+    pub fn is_retried(&self) -> bool {
+        !matches!(
+            self,
+            InnerCommand::Undo
+                | InnerCommand::ShowDay
+                | InnerCommand::Search(_)
+                | InnerCommand::Stats(_)
+        )
+    }
 }
 
-#[derive(Debug)]
+/// Serializable so failed ones can be kept in the [`crate::outbox`].
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NotionCommand {
     pub date: NaiveDate,
     pub username: String,
+    /// Where to reply to.
+    pub chat_id: ChatId,
     pub inner: InnerCommand,
+    /// All the updates merged into this, see [`crate::ledger`].
+    #[serde(default)]
+    pub update_ids: Vec<u32>,
 }
 
 impl NotionCommand {
@@ -72,16 +146,23 @@ impl NotionCommand {
         use NotionCommand as C;
         match (maybe_self, other) {
             (None, other) => Ok(other),
-            (Some(a), b) if a.date != b.date || a.username != b.username => Err((a, b)),
+            (Some(a), b)
+                if a.date != b.date || a.username != b.username || a.chat_id != b.chat_id =>
+            {
+                Err((a, b))
+            }
             (
                 Some(C {
                     date,
                     username,
+                    chat_id,
                     inner: inner1,
+                    update_ids: ids1,
                 }),
                 C {
                     inner: inner2,
                     username: u2,
+                    update_ids: ids2,
                     ..
                 },
             ) => {
@@ -98,18 +179,24 @@ impl NotionCommand {
                     Ok(inner) => Ok(C {
                         date,
                         username,
+                        chat_id,
                         inner,
+                        update_ids: ids1.into_iter().chain(ids2).collect(),
                     }),
                     Err((inner1, inner2)) => Err((
                         C {
                             date,
                             username,
+                            chat_id,
                             inner: inner1,
+                            update_ids: ids1,
                         },
                         C {
                             date,
                             username: u2,
+                            chat_id,
                             inner: inner2,
+                            update_ids: ids2,
                         },
                     )),
                 }
@@ -117,90 +204,511 @@ impl NotionCommand {
         }
     }
 
-    pub async fn execute(&self, notion: &mut NotionManager) -> anyhow::Result<()> {
+    /// When it fails, what was read of the day might be what's wrong, like a page deleted in
+    /// Notion, so it's read again next time.
+    pub async fn execute<B: JournalBackend>(
+        &self,
+        journals: &mut Journals<B>,
+        reply: &mut Reply,
+    ) -> anyhow::Result<()> {
+        let result = self.apply(journals, reply).await;
+        if result.is_err()
+            && let Ok(journal) = journals.user(&self.username)
+        {
+            journal.forget_day(self.date);
+        }
+        result
+    }
+
+    async fn apply<B: JournalBackend>(
+        &self,
+        journals: &mut Journals<B>,
+        reply: &mut Reply,
+    ) -> anyhow::Result<()> {
         let date = self.date;
-        let notion = notion.user(&self.username)?;
+        let aliases = journals.settings.aliases(&self.username);
+        let journal = journals.user(&self.username)?;
+        let mut ids = EntryIds::new();
+        let mut undos = vec![];
         match &self.inner {
-            &InnerCommand::Mood(mood) => notion.set_mood(mood, date).await,
-            InnerCommand::Text(texts) => notion.add_text(texts, date).await,
-            InnerCommand::People(people) => notion.add_people(people, date).await,
+            &InnerCommand::Mood(mood) => {
+                undos.push(Undo::Mood(journal.set_mood(mood, date).await?));
+                reply.mood(mood.clamp(0, 100));
+            }
+            InnerCommand::Text(texts) => {
+                ids = journal.add_text(texts, date).await?;
+                reply.entries(texts.len());
+                // One by one, in the order they were written.
+                undos.extend(texts.iter().filter_map(|entry| {
+                    let marker = entry.marker.clone()?;
+                    Some(Undo::Blocks {
+                        ids: ids.get(&marker)?.clone(),
+                        marker: Some(marker),
+                    })
+                }));
+            }
+            InnerCommand::People(people) => {
+                let added = journal.add_people(people, &aliases, date).await?;
+                undos.push(Undo::People(added.new.clone()));
+                reply.people(date, added)
+            }
+            InnerCommand::File(file, caption) => {
+                let mut blocks = vec![journal.add_file(file, date).await?];
+                reply.file();
+                if let Some(caption) = caption {
+                    ids = journal
+                        .add_text(std::slice::from_ref(caption), date)
+                        .await?;
+                    blocks.extend(ids.values().flatten().cloned());
+                    reply.entries(1);
+                }
+                undos.push(Undo::Blocks {
+                    ids: blocks,
+                    marker: caption.as_ref().and_then(|c| c.marker.clone()),
+                });
+            }
+            InnerCommand::EditText(entry, old_ids) => {
+                let new_ids = journal.edit_text(entry, old_ids, date).await?;
+                if let Some(marker) = &entry.marker {
+                    journals
+                        .undo
+                        .edited_or_log(&self.username, marker, old_ids, &new_ids);
+                }
+                ids.extend(entry.marker.clone().map(|marker| (marker, new_ids)));
+                reply.edited();
+            }
+            InnerCommand::EditPeople { removed, added } => {
+                reply.removed(journal.remove_people(removed, &aliases, date).await?);
+                reply.people(date, journal.add_people(added, &aliases, date).await?)
+            }
+            InnerCommand::AddPerson(name) => {
+                anyhow::ensure!(!name.is_empty(), "No name given");
+                journal.add_new_person(name, date).await?;
+                undos.push(Undo::People(vec![name.clone()]));
+                reply.people(
+                    date,
+                    AddedPeople {
+                        added: vec![name.clone()],
+                        ..Default::default()
+                    },
+                );
+            }
+            InnerCommand::Undo => return self.undo_last(journals, reply).await,
+            InnerCommand::ShowDay => match journal.read_day(date).await? {
+                Some(day) => reply.show(day.to_string()),
+                None => reply.show(format!("Nothing in the journal on {}.", date)),
+            },
+            InnerCommand::Search(query) => {
+                let hits = journal.search(&query.with_default_since(date)).await?;
+                reply.show(search::results(query, &hits));
+            }
+            InnerCommand::Stats(period) => {
+                let range = period.range(date);
+                let moods = journal.moods(range.0, range.1).await?;
+                match MoodStats::compute(*period, &moods) {
+                    Some(stats) if moods.len() > 1 => {
+                        reply.photo(stats::chart(*period, range, &moods)?, stats.to_string())
+                    }
+                    _ => reply.show(format!(
+                        "Not enough moods since {} for stats, set one with /mood.",
+                        range.0
+                    )),
+                }
+            }
+        }
+        for (marker, ids) in ids {
+            journals
+                .messages
+                .record_or_log(&self.username, &marker, date, Journaled::Text(ids));
+        }
+        for undo in undos {
+            journals.undo.push_or_log(&self.username, date, undo);
         }
+        Ok(())
     }
 
-    /// Returns wheter it was a success
-    pub async fn execute_or_log(&self, notion: &mut NotionManager) -> bool {
-        if let Err(e) = self.execute(notion).await {
+    /// Edits can't be undone, the last write before them is.
+    async fn undo_last<B: JournalBackend>(
+        &self,
+        journals: &mut Journals<B>,
+        reply: &mut Reply,
+    ) -> anyhow::Result<()> {
+        let Some(Applied { date, undo }) = journals.undo.last(&self.username).cloned() else {
+            reply.ignored("/undo, nothing left to undo".to_string());
+            return Ok(());
+        };
+        let journal = journals.user(&self.username)?;
+        match &undo {
+            Undo::Blocks { ids, marker } => {
+                journal.remove_blocks(ids, date).await?;
+                if let Some(marker) = marker {
+                    journals.messages.forget_or_log(&self.username, marker);
+                }
+            }
+            &Undo::Mood(mood) => journal.restore_mood(mood, date).await?,
+            Undo::People(people) => {
+                journal.remove_people(people, &Aliases::new(), date).await?;
+            }
+        }
+        if let Err(e) = journals.undo.pop(&self.username) {
+            log::error!("Failed to forget undone write: {:?}", e);
+        }
+        reply.undone(format!("{} on {}", undo, date));
+        Ok(())
+    }
+
+    /// Also tells the user about the error.
+    pub async fn execute_or_log<B: JournalBackend>(
+        &self,
+        journals: &mut Journals<B>,
+        reply: &mut Reply,
+    ) -> anyhow::Result<()> {
+        let result = self.execute(journals, reply).await;
+        if let Err(e) = &result {
             log::error!("Error handling command: {:?}, error: {:?}", self, e);
-            false
-        } else {
-            true
+            reply.failure(self.inner.to_string(), e);
         }
+        result
     }
 }
 
-impl NotionManager {
-    pub async fn new() -> anyhow::Result<Self> {
-        let usernames = std::env::var("TELEGRAM_USERNAMES")?;
-        let tokens = std::env::var("NOTION_TOKENS")?;
-        let db_ids = std::env::var("NOTION_DATABASE_IDS")?;
-        Ok(Self {
-            per_username: usernames
-                .split(',')
-                .zip(tokens.split(','))
-                .zip(db_ids.split(','))
-                .map(|((username, token), db_id)| {
-                    Ok((
-                        username.to_string(),
-                        NotionManagerForUser {
-                            api: Client::new(token.to_string(), None)?,
-                            initialized: false,
-                            db_id: DatabaseId(db_id.to_string()),
-                            page_cache: BTreeMap::new(),
-                            people: BTreeSet::new(),
+impl std::fmt::Display for NotionCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} on {}", self.inner, self.date)
+    }
+}
+
+impl std::fmt::Display for InnerCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InnerCommand::Mood(mood) => write!(f, "mood {}", mood),
+            InnerCommand::Text(texts) => write!(f, "{} entries", texts.len()),
+            InnerCommand::People(people) => write!(f, "people {}", people.join(", ")),
+            InnerCommand::AddPerson(name) => write!(f, "new person {}", name),
+            InnerCommand::File(file, _) => write!(f, "file {}", file.name),
+            InnerCommand::EditText(..) => write!(f, "edit"),
+            InnerCommand::EditPeople { added, .. } => {
+                write!(f, "people edit to {}", added.join(", "))
+            }
+            InnerCommand::Undo => write!(f, "undo"),
+            InnerCommand::ShowDay => write!(f, "reading the day"),
+            InnerCommand::Search(query) => write!(f, "search for {}", query.terms.join(" ")),
+            InnerCommand::Stats(_) => write!(f, "stats"),
+        }
+    }
+}
+
+/// Notion's limits, per text, texts in a block, and blocks in a request.
+const MAX_TEXT_LENGTH: usize = 2000;
+const MAX_RICH_TEXTS: usize = 100;
+const MAX_CHILDREN: usize = 100;
+
+fn text(content: String, link: Option<Link>, annotations: Option<Annotations>) -> RichText {
+    RichText::Text {
+        text: Text { content, link },
+        annotations,
+        plain_text: None,
+        href: None,
+    }
+}
+
+fn styled(span: &Span) -> RichText {
+    let mut annotations = Annotations::default();
+    let mut link = None;
+    for style in &span.styles {
+        match style {
+            Style::Bold => annotations.bold = true,
+            Style::Italic => annotations.italic = true,
+            Style::Underline => annotations.underline = true,
+            Style::Strikethrough => annotations.strikethrough = true,
+            Style::Code => annotations.code = true,
+            // Notion has no spoilers, at least they stand out.
+            Style::Spoiler => annotations.color = TextColor::GrayBackground,
+            Style::Link(url) => link = Some(Link { url: url.clone() }),
+            Style::CodeBlock { .. } => {}
+        }
+    }
+    let annotations = (annotations != Annotations::default()).then_some(annotations);
+    text(span.text.to_string(), link, annotations)
+}
+
+/// A paragraph starting with the time. Code blocks in the text become their own blocks after
+/// it.
+fn blocks(entry: &TextEntry) -> Vec<Block> {
+    let time = text(format!("[{}]", entry.time.format("%H:%M")), None, None);
+    let block = |block_type| Block {
+        block_type,
+        ..Default::default()
+    };
+    let mut parts = split(&entry.text, &entry.formatting).into_iter();
+    let mut rich_text = vec![time];
+    if let Some(formatting::Part::Paragraph(spans)) = parts.next() {
+        let mut spans = spans.iter().map(styled).peekable();
+        // The space goes in the first text, unless it has some style.
+        match spans.peek_mut() {
+            Some(RichText::Text {
+                text:
+                    Text {
+                        content,
+                        link: None,
+                    },
+                annotations: None,
+                ..
+            }) => content.insert(0, ' '),
+            _ => rich_text.push(text(" ".to_string(), None, None)),
+        }
+        rich_text.extend(spans);
+    }
+    // Long ones continue in the next paragraphs.
+    let paragraphs = |rich_text| {
+        within_limits(rich_text).into_iter().map(|rich_text| {
+            block(BlockType::Paragraph {
+                paragraph: ParagraphValue {
+                    rich_text,
+                    ..Default::default()
+                },
+            })
+        })
+    };
+    let mut result: Vec<_> = paragraphs(rich_text).collect();
+    for part in parts {
+        match part {
+            formatting::Part::Paragraph(spans) => {
+                result.extend(paragraphs(spans.iter().map(styled).collect()))
+            }
+            formatting::Part::CodeBlock { code, language } => {
+                // Telegram's languages are free text, Notion only knows some.
+                let language = language
+                    .and_then(|l| serde_json::from_value(json!(l.to_lowercase())).ok())
+                    .unwrap_or(Language::PlainText);
+                let code = vec![text(code.to_string(), None, None)];
+                result.extend(within_limits(code).into_iter().map(|rich_text| {
+                    block(BlockType::Code {
+                        code: CodeValue {
+                            caption: vec![],
+                            rich_text,
+                            language: language.clone(),
                         },
-                    ))
-                })
-                .collect::<anyhow::Result<_>>()?,
+                    })
+                }))
+            }
+        }
+    }
+    result
+}
+
+/// Splits at char boundaries, Notion counts in UTF-16 like JavaScript does.
+fn split_content(content: &str) -> Vec<&str> {
+    let mut pieces = vec![];
+    let mut start = 0;
+    let mut length = 0;
+    for (i, c) in content.char_indices() {
+        if length + c.len_utf16() > MAX_TEXT_LENGTH {
+            pieces.push(&content[start..i]);
+            start = i;
+            length = 0;
+        }
+        length += c.len_utf16();
+    }
+    pieces.push(&content[start..]);
+    pieces
+}
+
+/// Long texts become several with the same style, shown together as one, and when there
+/// are too many for a block they are split into several.
+fn within_limits(rich_text: Vec<RichText>) -> Vec<Vec<RichText>> {
+    let rich_text: Vec<_> = rich_text
+        .into_iter()
+        .flat_map(|rt| match rt {
+            RichText::Text {
+                text: Text { content, link },
+                annotations,
+                ..
+            } if content.encode_utf16().count() > MAX_TEXT_LENGTH => split_content(&content)
+                .into_iter()
+                .map(|piece| text(piece.to_string(), link.clone(), annotations.clone()))
+                .collect(),
+            rt => vec![rt],
         })
+        .collect();
+    if rich_text.is_empty() {
+        return vec![rich_text];
+    }
+    rich_text
+        .chunks(MAX_RICH_TEXTS)
+        .map(<[RichText]>::to_vec)
+        .collect()
+}
+
+fn mood_of(page: &Page, property: &str) -> Option<u8> {
+    match page.properties.get(property) {
+        Some(PageProperty::Number {
+            number: Some(number),
+            ..
+        }) => number.as_u64().map(|n| n.min(100) as u8),
+        _ => None,
     }
+}
 
-    pub fn user(&mut self, username: &str) -> anyhow::Result<&mut NotionManagerForUser> {
-        self.per_username.get_mut(username).context("Unknown user")
+fn multi_select_of(page: &Page, property: &str) -> Option<Vec<String>> {
+    match page.properties.get(property) {
+        Some(PageProperty::MultiSelect { multi_select, .. }) => Some(
+            multi_select
+                .iter()
+                .filter_map(|option| option.name.clone())
+                .collect(),
+        ),
+        _ => None,
     }
+}
 
-    pub fn user_is_known(&self, username: &str) -> bool {
-        self.per_username.contains_key(username)
+fn date_of(page: &Page, property: &str) -> Option<NaiveDate> {
+    match page.properties.get(property) {
+        Some(PageProperty::Date {
+            date: Some(DatePropertyValue {
+                start: Some(start), ..
+            }),
+            ..
+        }) => Some(match start {
+            DateOrDateTime::Date(date) => *date,
+            DateOrDateTime::DateTime(datetime) => datetime.date_naive(),
+        }),
+        _ => None,
     }
+}
 
-    pub async fn check_can_access_database(
-        &mut self,
-        usernames: BTreeSet<&str>,
-    ) -> anyhow::Result<()> {
-        for username in usernames {
-            self.user(username)?.check_can_access_database().await?;
+fn plain_text(rich_text: &[RichText]) -> String {
+    rich_text.iter().filter_map(RichText::plain_text).collect()
+}
+
+/// How a block reads in a chat. Ones the bot never writes are shown only if they have text.
+fn block_text(block: &Block) -> Option<String> {
+    let text = match &block.block_type {
+        BlockType::Paragraph { paragraph } => plain_text(&paragraph.rich_text),
+        BlockType::Code { code } => plain_text(&code.rich_text),
+        BlockType::Heading1 { heading_1: heading }
+        | BlockType::Heading2 { heading_2: heading }
+        | BlockType::Heading3 { heading_3: heading } => plain_text(&heading.rich_text),
+        BlockType::BulletedListItem { bulleted_list_item } => {
+            format!("• {}", plain_text(&bulleted_list_item.rich_text))
         }
-        Ok(())
+        BlockType::NumberedListItem { numbered_list_item } => {
+            format!("- {}", plain_text(&numbered_list_item.rich_text))
+        }
+        BlockType::Quote { quote } => format!("> {}", plain_text(&quote.rich_text)),
+        BlockType::ToDo { to_do } => plain_text(&to_do.rich_text),
+        BlockType::Image { .. } => "📎 image".to_string(),
+        BlockType::Video { .. } => "📎 video".to_string(),
+        BlockType::File { file } => format!("📎 {}", file.name),
+        _ => return None,
+    };
+    (!text.trim().is_empty()).then_some(text)
+}
+
+/// With the same headers notion-client uses.
+fn http_client(token: &str) -> anyhow::Result<reqwest::Client> {
+    let mut headers = HeaderMap::new();
+    headers.insert("Notion-Version", HeaderValue::from_static(NOTION_VERSION));
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    let mut auth = HeaderValue::from_str(&format!("Bearer {}", token))?;
+    auth.set_sensitive(true);
+    headers.insert(AUTHORIZATION, auth);
+    Ok(reqwest::Client::builder()
+        .default_headers(headers)
+        .build()?)
+}
+
+impl NotionManager {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        Self::from_config(config, NotionManagerForUser::from_env()?)
+    }
+}
+
+impl NotionManagerForUser {
+    /// Sets up users with the API URL (NOTION_API_URL) and request budget from the
+    /// environment, which are only read once.
+    pub fn from_env() -> anyhow::Result<impl Fn(&UserConfig) -> anyhow::Result<Self>> {
+        let base_url = std::env::var("NOTION_API_URL").ok();
+        let per_second = match std::env::var("NOTION_REQUESTS_PER_SECOND") {
+            Ok(value) => Some(
+                value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|n| *n > 0.0)
+                    .context("NOTION_REQUESTS_PER_SECOND must be a positive number")?,
+            ),
+            Err(_) => None,
+        };
+        Ok(move |user: &UserConfig| {
+            let manager = Self::new(
+                &user.notion_token()?,
+                user.database_id()?,
+                user.properties.clone(),
+                base_url.as_deref(),
+            )?;
+            Ok(match per_second {
+                Some(per_second) => manager.with_budget(RequestBudget::new(per_second, 10)),
+                None => manager,
+            })
+        })
     }
 }
 
 impl NotionManagerForUser {
+    /// If `base_url` is given, all requests go there instead of Notion, like
+    /// `http://127.0.0.1:8080/v1`.
+    pub fn new(
+        token: &str,
+        db_id: &str,
+        props: PropertyNames,
+        base_url: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            http: http_client(token)?,
+            base_url: base_url.unwrap_or(NOTION_URI).to_string(),
+            loaded_at: None,
+            people_ttl: PEOPLE_TTL,
+            db_id: DatabaseId(db_id.to_string()),
+            props,
+            page_cache: BTreeMap::new(),
+            people: BTreeSet::new(),
+            retrier: Retrier::default(),
+        })
+    }
+
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retrier.policy = policy;
+        self
+    }
+
+    pub fn with_people_ttl(mut self, ttl: Duration) -> Self {
+        self.people_ttl = ttl;
+        self
+    }
+
+    /// Each user has their own token, so their own limits.
+    pub fn with_budget(mut self, budget: RequestBudget) -> Self {
+        self.retrier.budget = budget;
+        self
+    }
+
     async fn check_can_access_database(&mut self) -> anyhow::Result<()> {
-        if self.initialized {
+        if self
+            .loaded_at
+            .is_some_and(|loaded_at| loaded_at.elapsed() < self.people_ttl)
+        {
             return Ok(());
         }
         let db = self
-            .api
-            .databases
-            .retrieve_a_database(&self.db_id.0)
+            .request(
+                "database",
+                true,
+                Method::GET,
+                &format!("databases/{}", self.db_id.0),
+                None,
+            )
             .await?;
-        if let Some(DatabaseProperty::MultiSelect { multi_select, .. }) = db.properties.get(PEOPLE)
-        {
-            for opt in &multi_select.options {
-                self.people.insert(opt.name.clone());
-            }
-        } else {
-            anyhow::bail!("Database has no people");
-        }
+        self.load_people(&db)?;
         log::info!(
             "Successfully accessed database: {}.",
             db.title
@@ -209,57 +717,256 @@ impl NotionManagerForUser {
                 .plain_text()
                 .context("Title is not plain text")?
         );
-        self.initialized = true;
+        self.loaded_at = Some(Instant::now());
         Ok(())
     }
 
+    /// Errors are the same as notion-client's, except that a `Retry-After` is kept.
+    async fn send<T: DeserializeOwned>(request: reqwest::RequestBuilder) -> anyhow::Result<T> {
+        let response = request
+            .send()
+            .await
+            .map_err(|source| NotionClientError::FailedToRequest { source })?;
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+            .map(Duration::from_secs);
+        let body = response
+            .text()
+            .await
+            .map_err(|source| NotionClientError::FailedToText { source })?;
+        let error = match serde_json::from_str(&body) {
+            Ok(Response::Success(result)) => return Ok(result),
+            Ok(Response::Error(error)) => NotionClientError::InvalidStatusCode { error },
+            Err(source) => NotionClientError::FailedToDeserialize { source, body },
+        };
+        Err(match retry_after {
+            Some(delay) => RetryAfter { delay, error }.into(),
+            None => error.into(),
+        })
+    }
+
+    /// Everything goes through [`Self::send`] instead of notion-client, so a `Retry-After` is
+    /// kept. The requests and responses are still notion-client's types.
+    async fn request<T: DeserializeOwned>(
+        &mut self,
+        what: &str,
+        idempotent: bool,
+        method: Method,
+        path: &str,
+        body: Option<String>,
+    ) -> anyhow::Result<T> {
+        let url = format!("{}/{}", self.base_url, path);
+        self.retrier
+            .run(what, idempotent, async || {
+                let mut request = self.http.request(method.clone(), &url);
+                if let Some(body) = &body {
+                    request = request.body(body.clone());
+                }
+                Self::send(request).await
+            })
+            .await
+    }
+
+    /// notion-client sends this as a POST, but Notion only accepts PATCH.
+    async fn update_database(
+        &mut self,
+        request: UpdateADatabaseRequest,
+    ) -> anyhow::Result<Database> {
+        self.request(
+            "database update",
+            true,
+            Method::PATCH,
+            &format!("databases/{}", self.db_id.0),
+            Some(serde_json::to_string(&request)?),
+        )
+        .await
+    }
+
+    /// notion-client doesn't know file uploads yet. Returns the id of the upload.
+    async fn upload_file(&mut self, file: &File) -> anyhow::Result<String> {
+        #[derive(Deserialize)]
+        struct FileUpload {
+            id: String,
+        }
+        let body = json!({ "filename": file.name, "content_type": file.content_type }).to_string();
+        // An upload that is never sent is just forgotten, so creating one twice is fine.
+        let upload: FileUpload = self
+            .request("upload", true, Method::POST, "file_uploads", Some(body))
+            .await?;
+        let url = format!("{}/file_uploads/{}/send", self.base_url, upload.id);
+        let _: FileUpload = self
+            .retrier
+            .run("upload", false, async || {
+                let part = Part::bytes(file.data.clone())
+                    .file_name(file.name.clone())
+                    .mime_str(&file.content_type)?;
+                Self::send(
+                    self.http
+                        .post(&url)
+                        .multipart(Form::new().part("file", part)),
+                )
+                .await
+            })
+            .await?;
+        Ok(upload.id)
+    }
+
+    /// All of them, page by page.
+    async fn list_blocks(&mut self, page_id: &str) -> anyhow::Result<Vec<Block>> {
+        let mut blocks = vec![];
+        let mut cursor = None;
+        loop {
+            let mut path = format!("blocks/{}/children", page_id);
+            if let Some(cursor) = &cursor {
+                path += &format!("?start_cursor={}", cursor);
+            }
+            let response: RetrieveBlockChilerenResponse = self
+                .request("blocks", true, Method::GET, &path, None)
+                .await?;
+            blocks.extend(response.results);
+            if !response.has_more {
+                break;
+            }
+            cursor = response.next_cursor;
+        }
+        Ok(blocks)
+    }
+
+    fn load_people(&mut self, db: &Database) -> anyhow::Result<()> {
+        if let Some(DatabaseProperty::MultiSelect { multi_select, .. }) =
+            db.properties.get(&self.props.people)
+        {
+            self.people = multi_select
+                .options
+                .iter()
+                .map(|opt| opt.name.clone())
+                .collect();
+            Ok(())
+        } else {
+            anyhow::bail!("Database has no people")
+        }
+    }
+
+    fn day_of(&self, date: NaiveDate, page: &Page, blocks: &[Block]) -> Day {
+        Day {
+            date,
+            mood: mood_of(page, &self.props.mood),
+            people: multi_select_of(page, &self.props.people).unwrap_or_default(),
+            tags: multi_select_of(page, &self.props.tags).unwrap_or_default(),
+            lines: blocks.iter().filter_map(block_text).collect(),
+        }
+    }
+
+    /// The bot's pages that match all the filters, sorted by date. Follows the cursor until
+    /// there are no more, or there are `max` of them.
+    async fn query_pages(
+        &mut self,
+        what: &str,
+        filters: Vec<FilterType>,
+        direction: SortDirection,
+        max: Option<usize>,
+    ) -> anyhow::Result<Vec<Page>> {
+        let filters: Vec<_> = std::iter::once(self.tag_filter()).chain(filters).collect();
+        let mut pages = vec![];
+        let mut cursor = None;
+        loop {
+            let mut request = QueryDatabaseRequestBuilder::default();
+            request
+                .filter(Filter::And {
+                    and: filters.clone(),
+                })
+                .sorts(vec![Sort::Property {
+                    property: self.props.date.clone(),
+                    direction: direction.clone(),
+                }]);
+            if let Some(cursor) = cursor.take() {
+                request.start_cursor(cursor);
+            }
+            let request = request.build()?;
+            let res: QueryDatabaseResponse = self
+                .request(
+                    what,
+                    true,
+                    Method::POST,
+                    &format!("databases/{}/query", self.db_id.0),
+                    Some(serde_json::to_string(&request)?),
+                )
+                .await?;
+            pages.extend(res.results);
+            if let Some(max) = max
+                && pages.len() >= max
+            {
+                pages.truncate(max);
+                return Ok(pages);
+            }
+            if !res.has_more {
+                return Ok(pages);
+            }
+            cursor = res.next_cursor;
+        }
+    }
+
+    /// Only the bot's pages, the database might have others.
+    fn tag_filter(&self) -> FilterType {
+        FilterType::Property {
+            property: self.props.tags.clone(),
+            condition: PropertyCondition::MultiSelect(MultiSelectCondition::Contains(
+                self.props.stream_of_consciousness.clone(),
+            )),
+        }
+    }
+
+    /// The page of that day, if there is one already.
+    async fn find_page(&mut self, date: NaiveDate) -> anyhow::Result<Option<Page>> {
+        let filters = vec![
+            FilterType::Property {
+                property: self.props.date.clone(),
+                condition: PropertyCondition::Date(DateCondition::Equals(
+                    date.and_time(NaiveTime::MIN).and_utc(),
+                )),
+            },
+            self.tag_filter(),
+        ];
+        let request = QueryDatabaseRequestBuilder::default()
+            .filter(Filter::And { and: filters })
+            .build()?;
+        let res: QueryDatabaseResponse = self
+            .request(
+                "query",
+                true,
+                Method::POST,
+                &format!("databases/{}/query", self.db_id.0),
+                Some(serde_json::to_string(&request)?),
+            )
+            .await?;
+        let page = res.results.into_iter().next();
+        if let Some(page) = &page {
+            log::debug!("Found existing page with date: {}, url: {}", date, page.url);
+        }
+        Ok(page)
+    }
+
     /// First try to get the previously created page with same date. Otherwise, create a new one.
     /// Always cache in case we have multiple messages.
     async fn get_or_create_page(&mut self, date: NaiveDate) -> Result<&Page, anyhow::Error> {
-        let entry = self.page_cache.entry(date);
-        Ok(match entry {
-            Entry::Occupied(occupied_entry) => occupied_entry.into_mut(),
-            Entry::Vacant(vacant_entry) => {
-                let filters = vec![
-                    FilterType::Property {
-                        property: DATE.to_string(),
-                        condition: PropertyCondition::Date(DateCondition::Equals(
-                            date.and_time(NaiveTime::MIN).and_utc(),
-                        )),
-                    },
-                    FilterType::Property {
-                        property: TAGS.to_string(),
-                        condition: PropertyCondition::MultiSelect(MultiSelectCondition::Contains(
-                            STREAM_OF_CONSCIOUSNESS.to_string(),
-                        )),
-                    },
-                ];
-                let res = self
-                    .api
-                    .databases
-                    .query_a_database(
-                        &self.db_id.0,
-                        QueryDatabaseRequestBuilder::default()
-                            .filter(Filter::And { and: filters })
-                            .build()?,
-                    )
-                    .await?;
-                let id = if let Some(page) = res.results.into_iter().next() {
-                    log::debug!("Found existing page with date: {}, url: {}", date, page.url);
-                    page
-                } else {
+        if !self.page_cache.contains_key(&date) {
+            let page = match self.find_page(date).await? {
+                Some(page) => page,
+                None => {
                     log::debug!("Creating new page with date: {}", date);
                     let properties = btreemap! {
-                        TAGS.to_string() =>
+                        self.props.tags.clone() =>
                             PageProperty::MultiSelect {
                                 id: None,
                                 multi_select: vec![SelectPropertyValue {
-                                    name: Some(STREAM_OF_CONSCIOUSNESS.to_string()),
+                                    name: Some(self.props.stream_of_consciousness.clone()),
                                     color: None,
                                     id: None,
                                 }],
                             },
-                        DATE.to_string() =>
+                        self.props.date.clone() =>
                             PageProperty::Date {
                                 id: None,
                                 date: Some(DatePropertyValue {
@@ -268,7 +975,7 @@ impl NotionManagerForUser {
                                     time_zone: None,
                                 }),
                             },
-                        TITLE.to_string() =>
+                        self.props.title.clone() =>
                             PageProperty::Title {
                                 id: None,
                                 title: vec![RichText::Text {
@@ -282,73 +989,93 @@ impl NotionManagerForUser {
                                 }],
                             },
                     };
-                    self.api
-                        .pages
-                        .create_a_page(
-                            CreateAPageRequestBuilder::default()
-                                .parent(Parent::DatabaseId {
-                                    database_id: self.db_id.0.clone(),
-                                })
-                                .properties(properties)
-                                .icon(Icon::Emoji(Emoji {
-                                    emoji: "💭".to_string(),
-                                }))
-                                .build()?,
-                        )
-                        .await?
-                };
-                vacant_entry.insert(id)
-            }
-        })
+                    let request = CreateAPageRequestBuilder::default()
+                        .parent(Parent::DatabaseId {
+                            database_id: self.db_id.0.clone(),
+                        })
+                        .properties(properties)
+                        .icon(Icon::Emoji(Emoji {
+                            emoji: "💭".to_string(),
+                        }))
+                        .build()?;
+                    // If it might have been created, the next query finds it.
+                    self.request(
+                        "page creation",
+                        false,
+                        Method::POST,
+                        "pages",
+                        Some(serde_json::to_string(&request)?),
+                    )
+                    .await?
+                }
+            };
+            self.page_cache.insert(date, page);
+        }
+        Ok(&self.page_cache[&date])
     }
 
-    fn find_person(&self, name: &str) -> Option<&str> {
-        if let Some(name) = self.people.get(name) {
-            return Some(name);
-        }
-        let (mut min_dist, mut which) = (usize::MAX, None);
-        let name = unidecode(name).to_ascii_lowercase();
-        for person in &self.people {
-            let lower_person = unidecode(person).to_ascii_lowercase();
-            let dist = lower_person
-                .split_whitespace()
-                .chain(std::iter::once(lower_person.as_str()))
-                .map(|p| edit_distance::edit_distance(name.as_str(), p))
-                .min()
-                .unwrap_or(usize::MAX);
-            if dist < min_dist && (dist == 0 || dist < 4.min(name.len().saturating_sub(3))) {
-                min_dist = dist;
-                which = Some(person.as_str());
-            } else if dist == min_dist {
-                which = None; // Ambiguous
-            }
-        }
-        if which.is_none() {
-            log::warn!("Didn't find person: {}", name);
-        }
-        which
+    /// Returns the mood before, as last seen in the page.
+    async fn write_mood(
+        &mut self,
+        mood: Option<u8>,
+        date: NaiveDate,
+    ) -> anyhow::Result<Option<u8>> {
+        let mood_prop = self.props.mood.clone();
+        let page = self.get_or_create_page(date).await?;
+        let before = mood_of(page, &mood_prop);
+        let id = page.id.clone();
+        let request = UpdatePagePropertiesRequestBuilder::default()
+            .properties(btreemap! {
+                mood_prop =>
+                    Some(PageProperty::Number {
+                        id: None,
+                        number: mood.map(Into::into),
+                    }),
+            })
+            .build()?;
+        let page = self
+            .request(
+                "mood update",
+                true,
+                Method::PATCH,
+                &format!("pages/{}", id),
+                Some(serde_json::to_string(&request)?),
+            )
+            .await?;
+        self.page_cache.insert(date, page);
+        Ok(before)
     }
 
-    pub async fn add_people(&mut self, people: &[String], date: NaiveDate) -> anyhow::Result<()> {
-        log::trace!("Adding people: {:?}", people);
-        let people: Vec<String> = people
+    /// Adds to the people already in the page, returning the ones that weren't there.
+    async fn write_people(
+        &mut self,
+        people: &[String],
+        date: NaiveDate,
+    ) -> anyhow::Result<Vec<String>> {
+        let before = self
+            .update_people(date, |all| all.extend(people.iter().cloned()))
+            .await?;
+        Ok(people
             .iter()
-            .filter_map(|p| self.find_person(p))
-            .map(ToString::to_string)
-            .collect();
+            .filter(|person| !before.contains(*person))
+            .cloned()
+            .collect())
+    }
+
+    /// Changes the people already in the page, returning the ones before.
+    async fn update_people(
+        &mut self,
+        date: NaiveDate,
+        change: impl FnOnce(&mut BTreeSet<String>),
+    ) -> anyhow::Result<BTreeSet<String>> {
+        let people_prop = self.props.people.clone();
         let page = self.get_or_create_page(date).await?;
-        let all_people: BTreeSet<String> =
-            if let Some(PageProperty::MultiSelect { multi_select, .. }) =
-                page.properties.get(PEOPLE)
-            {
-                multi_select
-                    .iter()
-                    .filter_map(|p| p.name.clone())
-                    .chain(people)
-                    .collect()
-            } else {
-                anyhow::bail!("Page has no people property")
-            };
+        let mut all_people: BTreeSet<String> = multi_select_of(page, &people_prop)
+            .context("Page has no people property")?
+            .into_iter()
+            .collect();
+        let before = all_people.clone();
+        change(&mut all_people);
         let multi_select = all_people
             .into_iter()
             .map(|name| SelectPropertyValue {
@@ -358,83 +1085,392 @@ impl NotionManagerForUser {
             })
             .collect();
         let id = page.id.clone();
-        self.api
-            .pages
-            .update_page_properties(
-                &id,
-                UpdatePagePropertiesRequestBuilder::default()
-                    .properties(btreemap! {
-                        PEOPLE.to_string() =>
-                            Some(PageProperty::MultiSelect {
-                                id: None,
-                                // TODO: Show errors to user.
-                                multi_select,
-                            }),
-                    })
-                    .build()?,
+        let request = UpdatePagePropertiesRequestBuilder::default()
+            .properties(btreemap! {
+                self.props.people.clone() =>
+                    Some(PageProperty::MultiSelect {
+                        id: None,
+                        multi_select,
+                    }),
+            })
+            .build()?;
+        let page = self
+            .request(
+                "people update",
+                true,
+                Method::PATCH,
+                &format!("pages/{}", id),
+                Some(serde_json::to_string(&request)?),
             )
             .await?;
-        Ok(())
+        // Later merges need to see these people.
+        self.page_cache.insert(date, page);
+        Ok(before)
     }
+}
 
-    pub async fn set_mood(&mut self, mood: u8, date: NaiveDate) -> anyhow::Result<()> {
-        let mood = mood.clamp(0, 100);
-        log::trace!("Setting mood to Notion: {}", mood);
+impl JournalBackend for NotionManagerForUser {
+    async fn check_can_access(&mut self) -> anyhow::Result<()> {
+        self.check_can_access_database().await
+    }
+
+    async fn get_or_create_day(&mut self, date: NaiveDate) -> anyhow::Result<String> {
+        Ok(self.get_or_create_page(date).await?.id.clone())
+    }
+
+    fn forget_day(&mut self, date: NaiveDate) {
+        self.page_cache.remove(&date);
+    }
+
+    async fn add_people(
+        &mut self,
+        people: &[String],
+        aliases: &Aliases,
+        date: NaiveDate,
+    ) -> anyhow::Result<AddedPeople> {
+        log::trace!("Adding people: {:?}", people);
+        let mut result = AddedPeople::resolve(&self.people, aliases, people);
+        result.new = self.write_people(&result.added, date).await?;
+        Ok(result)
+    }
+
+    async fn remove_people(
+        &mut self,
+        people: &[String],
+        aliases: &Aliases,
+        date: NaiveDate,
+    ) -> anyhow::Result<Vec<String>> {
+        log::trace!("Removing people: {:?}", people);
+        let removed = AddedPeople::resolve(&self.people, aliases, people).added;
+        let before = self
+            .update_people(date, |all| all.retain(|person| !removed.contains(person)))
+            .await?;
+        Ok(removed
+            .into_iter()
+            .filter(|person| before.contains(person))
+            .collect())
+    }
+
+    async fn add_file(&mut self, file: &File, date: NaiveDate) -> anyhow::Result<String> {
+        log::trace!("Adding file to Notion: {:?}", file);
         let id = self.get_or_create_page(date).await?.id.clone();
-        self.api
-            .pages
-            .update_page_properties(
-                &id,
-                UpdatePagePropertiesRequestBuilder::default()
-                    .properties(btreemap! {
-                        MOOD.to_string() =>
-                            Some(PageProperty::Number {
-                                id: None,
-                                number: Some(mood.into()),
-                            }),
-                    })
-                    .build()?,
+        let upload_id = self.upload_file(file).await?;
+        let kind = match file.kind {
+            AttachmentKind::Image => "image",
+            AttachmentKind::Video => "video",
+            AttachmentKind::File => "file",
+        };
+        // Also by hand, as notion-client's blocks can't point to uploads.
+        let body = json!({ "children": [{
+            "object": "block",
+            "type": kind,
+            kind: { "type": "file_upload", "file_upload": { "id": upload_id } },
+        }] })
+        .to_string();
+        let response: serde_json::Value = self
+            .request(
+                "file block",
+                false,
+                Method::PATCH,
+                &format!("blocks/{}/children", id),
+                Some(body),
             )
             .await?;
+        response["results"][0]["id"]
+            .as_str()
+            .map(ToString::to_string)
+            .context("No block in the response")
+    }
+
+    /// Adds the option to the database, refreshing the known people, then to the page.
+    async fn add_new_person(&mut self, name: &str, date: NaiveDate) -> anyhow::Result<()> {
+        log::trace!("Adding new person: {}", name);
+        // Fresh, so we don't lose options added elsewhere meanwhile.
+        let db: Database = self
+            .request(
+                "database",
+                true,
+                Method::GET,
+                &format!("databases/{}", self.db_id.0),
+                None,
+            )
+            .await?;
+        let Some(DatabaseProperty::MultiSelect {
+            id,
+            name: prop_name,
+            multi_select,
+        }) = db.properties.get(&self.props.people).cloned()
+        else {
+            anyhow::bail!("Database has no people")
+        };
+        if multi_select.options.iter().any(|opt| opt.name == name) {
+            self.load_people(&db)?;
+        } else {
+            let mut options = multi_select.options;
+            options.push(OptionValue {
+                name: name.to_string(),
+                color: None,
+                id: None,
+            });
+            let db = self
+                .update_database(
+                    UpdateADatabaseRequestBuilder::default()
+                        // These are sent even if None, and Notion doesn't like nulls.
+                        .title(db.title)
+                        .description(db.description)
+                        .properties(btreemap! {
+                            self.props.people.clone() => Some(DatabaseProperty::MultiSelect {
+                                id,
+                                name: prop_name,
+                                multi_select: DatabaseSelectPropertyValue { options },
+                            }),
+                        })
+                        .build()?,
+                )
+                .await?;
+            self.load_people(&db)?;
+        }
+        self.write_people(&[name.to_string()], date).await?;
         Ok(())
     }
 
-    pub async fn add_text(
+    async fn set_mood(&mut self, mood: u8, date: NaiveDate) -> anyhow::Result<Option<u8>> {
+        let mood = mood.clamp(0, 100);
+        log::trace!("Setting mood to Notion: {}", mood);
+        self.write_mood(Some(mood), date).await
+    }
+
+    async fn restore_mood(&mut self, mood: Option<u8>, date: NaiveDate) -> anyhow::Result<()> {
+        log::trace!("Restoring mood in Notion: {:?}", mood);
+        self.write_mood(mood, date).await?;
+        Ok(())
+    }
+
+    /// The ids are of the blocks, in order.
+    async fn add_text(
         &mut self,
-        all_text: &[(String, NaiveTime)],
+        all_text: &[TextEntry],
         date: NaiveDate,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<EntryIds> {
         log::trace!("Adding text to Notion: {:?}", all_text);
         let id = self.get_or_create_page(date).await?.id.clone();
-        let blocks = all_text
-            .iter()
-            .map(|(text, time)| Block {
-                block_type: BlockType::Paragraph {
-                    paragraph: ParagraphValue {
-                        rich_text: vec![RichText::Text {
-                            text: Text {
-                                content: format!("[{}] {}", time.format("%H:%M"), text),
-                                link: None,
-                            },
-                            annotations: None,
-                            plain_text: None,
-                            href: None,
-                        }],
-                        ..Default::default()
-                    },
-                },
-                ..Default::default()
-            })
-            .collect();
-        self.api
-            .blocks
-            .append_block_children(
-                &id,
-                AppendBlockChildrenRequestBuilder::default()
-                    .children(blocks)
-                    .build()?,
+        let mut ids = EntryIds::new();
+        // Entries are only split across requests when too big for one, so a failure leaves
+        // at most one of them half written.
+        let mut chunks: Vec<Vec<(Option<&String>, Block)>> = vec![];
+        for entry in all_text {
+            let entry_blocks: Vec<_> = blocks(entry)
+                .into_iter()
+                .map(|block| (entry.marker.as_ref(), block))
+                .collect();
+            match chunks.last_mut() {
+                Some(chunk) if chunk.len() + entry_blocks.len() <= MAX_CHILDREN => {
+                    chunk.extend(entry_blocks)
+                }
+                _ => chunks.extend(entry_blocks.chunks(MAX_CHILDREN).map(<[_]>::to_vec)),
+            }
+        }
+        // In order, each after the previous worked, so the page never has them out of order.
+        for chunk in chunks {
+            let (owners, children): (Vec<_>, Vec<_>) = chunk.into_iter().unzip();
+            let request = AppendBlockChildrenRequestBuilder::default()
+                .children(children)
+                .build()?;
+            // Not retried when it might have worked, that would write the texts twice.
+            let response: AppendBlockChildrenResponse = self
+                .request(
+                    "text",
+                    false,
+                    Method::PATCH,
+                    &format!("blocks/{}/children", id),
+                    Some(serde_json::to_string(&request)?),
+                )
+                .await?;
+            for (owner, block) in owners.into_iter().zip(response.results) {
+                if let (Some(marker), Some(id)) = (owner, block.id) {
+                    ids.entry(marker.clone()).or_default().push(id);
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    /// The first block, with the time, is changed in place. The rest are deleted and added
+    /// again after it, as Notion can't change the type of a block.
+    async fn edit_text(
+        &mut self,
+        entry: &TextEntry,
+        ids: &[String],
+        date: NaiveDate,
+    ) -> anyhow::Result<Vec<String>> {
+        log::trace!("Editing text in Notion: {:?}", entry);
+        let (first_id, old_rest) = ids.split_first().context("No blocks to edit")?;
+        let mut new_blocks = blocks(entry).into_iter();
+        let first = new_blocks.next().context("Empty entry")?;
+        let mut body = serde_json::to_value(&first.block_type)?;
+        if let Some(body) = body.as_object_mut() {
+            body.remove("type");
+        }
+        let body = body.to_string();
+        let _: serde_json::Value = self
+            .request(
+                "text edit",
+                true,
+                Method::PATCH,
+                &format!("blocks/{}", first_id),
+                Some(body),
             )
             .await?;
+        for id in old_rest {
+            let _: Block = self
+                .request(
+                    "block deletion",
+                    false,
+                    Method::DELETE,
+                    &format!("blocks/{}", id),
+                    None,
+                )
+                .await?;
+        }
+        let page_id = self.get_or_create_page(date).await?.id.clone();
+        let mut new_ids = vec![first_id.clone()];
+        let rest: Vec<_> = new_blocks.collect();
+        for chunk in rest.chunks(MAX_CHILDREN) {
+            let request = AppendBlockChildrenRequestBuilder::default()
+                .children(chunk.to_vec())
+                .after(new_ids.last().unwrap().clone())
+                .build()?;
+            let response: AppendBlockChildrenResponse = self
+                .request(
+                    "text",
+                    false,
+                    Method::PATCH,
+                    &format!("blocks/{}/children", page_id),
+                    Some(serde_json::to_string(&request)?),
+                )
+                .await?;
+            new_ids.extend(response.results.into_iter().filter_map(|block| block.id));
+        }
+        Ok(new_ids)
+    }
+
+    /// Always fresh, it might have been changed in Notion.
+    async fn read_day(&mut self, date: NaiveDate) -> anyhow::Result<Option<Day>> {
+        let Some(page) = self.find_page(date).await? else {
+            return Ok(None);
+        };
+        let blocks = self.list_blocks(&page.id).await?;
+        let day = self.day_of(date, &page, &blocks);
+        self.page_cache.insert(date, page);
+        Ok(Some(day))
+    }
+
+    /// The pages first, then the blocks of each. Pages aren't cached, there could be a lot.
+    async fn all_days(&mut self) -> anyhow::Result<Vec<Day>> {
+        let pages = self
+            .query_pages("export query", vec![], SortDirection::Ascending, None)
+            .await?;
+        let mut days = vec![];
+        let total = pages.len();
+        for (i, page) in pages.into_iter().enumerate() {
+            let Some(date) = date_of(&page, &self.props.date) else {
+                log::warn!("Skipping page without date: {}", page.url);
+                continue;
+            };
+            log::info!("Reading {} ({}/{})", date, i + 1, total);
+            let blocks = self.list_blocks(&page.id).await?;
+            days.push(self.day_of(date, &page, &blocks));
+        }
+        Ok(days)
+    }
+
+    /// Reads the pages one by one, newest first, as Notion's own search can't be limited to
+    /// a database nor ignores accents.
+    async fn search(&mut self, search: &Search) -> anyhow::Result<Vec<Hit>> {
+        let mut filters = vec![];
+        if let Some(since) = search.since {
+            filters.push(FilterType::Property {
+                property: self.props.date.clone(),
+                condition: PropertyCondition::Date(DateCondition::OnOrAfter(
+                    since.and_time(NaiveTime::MIN).and_utc(),
+                )),
+            });
+        }
+        let pages = self
+            .query_pages("search", filters, SortDirection::Descending, Some(MAX_DAYS))
+            .await?;
+        let mut hits = vec![];
+        for page in pages {
+            let Some(date) = date_of(&page, &self.props.date) else {
+                continue;
+            };
+            let lines: Vec<_> = self
+                .list_blocks(&page.id)
+                .await?
+                .iter()
+                .filter_map(block_text)
+                .collect();
+            for snippet in search.find(&lines) {
+                hits.push(Hit {
+                    date,
+                    snippet,
+                    url: Some(page.url.clone()),
+                });
+                if hits.len() == MAX_HITS {
+                    return Ok(hits);
+                }
+            }
+        }
+        Ok(hits)
+    }
+
+    async fn moods(
+        &mut self,
+        first: NaiveDate,
+        last: NaiveDate,
+    ) -> anyhow::Result<Vec<(NaiveDate, u8)>> {
+        let filters = vec![
+            FilterType::Property {
+                property: self.props.date.clone(),
+                condition: PropertyCondition::Date(DateCondition::OnOrAfter(
+                    first.and_time(NaiveTime::MIN).and_utc(),
+                )),
+            },
+            FilterType::Property {
+                property: self.props.date.clone(),
+                condition: PropertyCondition::Date(DateCondition::OnOrBefore(
+                    last.and_time(NaiveTime::MIN).and_utc(),
+                )),
+            },
+        ];
+        let pages = self
+            .query_pages("moods query", filters, SortDirection::Ascending, None)
+            .await?;
+        Ok(pages
+            .iter()
+            .filter_map(|page| {
+                Some((
+                    date_of(page, &self.props.date)?,
+                    mood_of(page, &self.props.mood)?,
+                ))
+            })
+            .collect())
+    }
+
+    /// Archives them, Notion keeps them in the trash for a while.
+    async fn remove_blocks(&mut self, ids: &[String], _date: NaiveDate) -> anyhow::Result<()> {
+        log::trace!("Removing blocks from Notion: {:?}", ids);
+        for id in ids {
+            let _: Block = self
+                .request(
+                    "block deletion",
+                    false,
+                    Method::DELETE,
+                    &format!("blocks/{}", id),
+                    None,
+                )
+                .await?;
+        }
         Ok(())
     }
 }
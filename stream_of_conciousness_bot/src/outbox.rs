@@ -0,0 +1,168 @@
+//! Commands that failed in a batch where others succeeded. The batch is acked, so without this
+//! they'd be lost. They are retried with exponential backoff, and after [`MAX_ATTEMPTS`] they
+//! go to the dead letters, and the user is told.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::Bot;
+
+use crate::{
+    journal::{JournalBackend, Journals},
+    notion_manager::{InnerCommand, NotionCommand},
+    reply::{Replies, Reply},
+    state_file,
+};
+
+/// Counting the first one, which happened in the batch.
+pub const MAX_ATTEMPTS: u32 = 6;
+/// Doubles after each attempt, so the last one is about an hour after the first.
+const FIRST_RETRY_DELAY: Duration = Duration::minutes(2);
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OutboxEntry {
+    pub cmd: NotionCommand,
+    pub attempts: u32,
+    pub next_attempt: DateTime<Utc>,
+    pub last_error: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct OutboxData {
+    pending: Vec<OutboxEntry>,
+    /// Never retried again, only kept so nothing is really lost.
+    dead: Vec<OutboxEntry>,
+}
+
+pub struct Outbox {
+    path: PathBuf,
+    data: OutboxData,
+}
+
+impl Outbox {
+    /// From `outbox.json` in the state directory.
+    pub fn load(state_dir: &Path) -> anyhow::Result<Self> {
+        Self::open(state_dir.join("outbox.json"))
+    }
+
+    pub fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let data = state_file::read(&path)?;
+        Ok(Self { path, data })
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        state_file::write(&self.path, &self.data)
+    }
+
+    pub fn pending(&self) -> &[OutboxEntry] {
+        &self.data.pending
+    }
+
+    pub fn dead(&self) -> &[OutboxEntry] {
+        &self.data.dead
+    }
+
+    /// Logs instead of failing, as the rest of the batch is already written. They are lost.
+    pub fn add_or_log(
+        &mut self,
+        cmds: impl IntoIterator<Item = (NotionCommand, anyhow::Error)>,
+        now: DateTime<Utc>,
+    ) {
+        if let Err(e) = self.add(cmds, now) {
+            log::error!("Failed to save the outbox: {:?}", e);
+        }
+    }
+
+    /// After the first attempt failed. Files are downloaded again when retrying.
+    pub fn add(
+        &mut self,
+        cmds: impl IntoIterator<Item = (NotionCommand, anyhow::Error)>,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        self.data
+            .pending
+            .extend(cmds.into_iter().map(|(cmd, error)| OutboxEntry {
+                cmd,
+                attempts: 1,
+                next_attempt: now + FIRST_RETRY_DELAY,
+                last_error: format!("{:#}", error),
+            }));
+        self.save()
+    }
+
+    /// Removes the entries that should be tried now. They must be given back with
+    /// [`Self::failed_again`], or they are gone on the next save.
+    pub fn take_due(&mut self, now: DateTime<Utc>) -> Vec<OutboxEntry> {
+        let (due, later) = std::mem::take(&mut self.data.pending)
+            .into_iter()
+            .partition(|entry| entry.next_attempt <= now);
+        self.data.pending = later;
+        due
+    }
+
+    /// Returns the entry back if it is dead now.
+    pub fn failed_again(
+        &mut self,
+        mut entry: OutboxEntry,
+        error: &anyhow::Error,
+        now: DateTime<Utc>,
+    ) -> Option<&OutboxEntry> {
+        entry.attempts += 1;
+        entry.last_error = format!("{:#}", error);
+        if entry.attempts >= MAX_ATTEMPTS {
+            self.data.dead.push(entry);
+            self.data.dead.last()
+        } else {
+            entry.next_attempt = now + FIRST_RETRY_DELAY * 2_i32.pow(entry.attempts - 1);
+            self.data.pending.push(entry);
+            None
+        }
+    }
+}
+
+/// Retries what is due, replying to the user with what worked, and what was given up on.
+pub async fn retry_due<B: JournalBackend>(
+    bot: &Bot,
+    journals: &mut Journals<B>,
+) -> anyhow::Result<()> {
+    let now = Utc::now();
+    let due = journals.outbox.take_due(now);
+    if due.is_empty() {
+        return Ok(());
+    }
+    log::info!("Retrying {} commands from the outbox", due.len());
+    let mut replies = Replies::default();
+    for mut entry in due {
+        // Failures are only told when giving up.
+        let mut reply = Reply::default();
+        let result = async {
+            journals
+                .user(&entry.cmd.username)?
+                .check_can_access()
+                .await?;
+            if let InnerCommand::File(file, _) = &mut entry.cmd.inner {
+                file.download_again(bot).await?;
+            }
+            entry.cmd.execute(journals, &mut reply).await
+        }
+        .await;
+        match result {
+            Ok(()) => replies.chat(entry.cmd.chat_id).merge(reply),
+            Err(e) => {
+                log::warn!("Retry of {:?} failed: {:?}", entry.cmd, e);
+                if let Some(dead) = journals.outbox.failed_again(entry, &e, now) {
+                    log::error!("Giving up on {:?}", dead.cmd);
+                    replies.chat(dead.cmd.chat_id).gave_up(
+                        dead.cmd.to_string(),
+                        dead.attempts,
+                        &dead.last_error,
+                    );
+                }
+            }
+        }
+    }
+    journals.outbox.save()?;
+    replies.send(bot).await;
+    Ok(())
+}
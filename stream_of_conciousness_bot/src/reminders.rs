@@ -0,0 +1,250 @@
+//! The only time the bot talks first: asking for the mood at the time each user chose, with a
+//! keyboard to answer it right away. It's sent by the first run after that time, so when
+//! running on a schedule it's only as punctual as the schedule. Days that already have a mood
+//! are skipped, checked against the journal.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::{
+    prelude::*,
+    types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup},
+};
+
+use crate::{
+    commands::{Command, Content, IncomingMessage},
+    disambiguation,
+    journal::{JournalBackend, Journals},
+    state_file,
+};
+
+/// Callback data is this, the day and the mood. Disambiguation data starts with a date.
+const CALLBACK_PREFIX: &str = "mood ";
+const MOODS: [(&str, u8); 5] = [("😞", 10), ("🙁", 30), ("😐", 50), ("🙂", 70), ("😄", 90)];
+
+/// Written as `21:00`, or `off` to turn off a configured one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Reminder {
+    Off,
+    At(NaiveTime),
+}
+
+impl FromStr for Reminder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("off") {
+            return Ok(Self::Off);
+        }
+        NaiveTime::parse_from_str(s, "%H:%M")
+            .map(Self::At)
+            .map_err(|_| anyhow::anyhow!("Invalid reminder {:?}, use 21:00 or off", s))
+    }
+}
+
+impl TryFrom<String> for Reminder {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Reminder> for String {
+    fn from(reminder: Reminder) -> Self {
+        reminder.to_string()
+    }
+}
+
+impl std::fmt::Display for Reminder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Off => write!(f, "off"),
+            Self::At(time) => write!(f, "{}", time.format("%H:%M")),
+        }
+    }
+}
+
+/// Times are compared from the day start, so a reminder at 1am with the day starting at 6am is
+/// at the end of the day, not due all day long.
+pub fn is_due(at: NaiveTime, now: NaiveTime, day_start_hour: u32) -> bool {
+    let start = NaiveTime::from_hms_opt(day_start_hour, 0, 0).unwrap_or(NaiveTime::MIN);
+    let since_start = |time: NaiveTime| (time - start).num_minutes().rem_euclid(24 * 60);
+    since_start(now) >= since_start(at)
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct UserReminders {
+    /// Where the user last set their reminder.
+    chat_id: Option<ChatId>,
+    /// The last day that was asked about, or skipped as it had a mood already.
+    last_day: Option<NaiveDate>,
+}
+
+pub struct Reminders {
+    path: PathBuf,
+    per_user: BTreeMap<String, UserReminders>,
+}
+
+impl Reminders {
+    /// From `reminders.json` in the state directory.
+    pub fn load(state_dir: &Path) -> anyhow::Result<Self> {
+        Self::open(state_dir.join("reminders.json"))
+    }
+
+    pub fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let per_user = state_file::read(&path)?;
+        Ok(Self { path, per_user })
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        state_file::write(&self.path, &self.per_user)
+    }
+
+    pub fn chat(&self, username: &str) -> Option<ChatId> {
+        self.per_user.get(username)?.chat_id
+    }
+
+    pub fn set_chat(&mut self, username: &str, chat_id: ChatId) -> anyhow::Result<()> {
+        let user = self.per_user.entry(username.to_string()).or_default();
+        if user.chat_id == Some(chat_id) {
+            return Ok(());
+        }
+        user.chat_id = Some(chat_id);
+        self.save()
+    }
+
+    pub fn set_chat_or_log(&mut self, username: &str, chat_id: ChatId) {
+        if let Err(e) = self.set_chat(username, chat_id) {
+            log::error!("Failed to record chat of {}: {:?}", username, e);
+        }
+    }
+
+    pub fn last_day(&self, username: &str) -> Option<NaiveDate> {
+        self.per_user.get(username)?.last_day
+    }
+
+    pub fn set_last_day(&mut self, username: &str, day: Option<NaiveDate>) -> anyhow::Result<()> {
+        self.per_user
+            .entry(username.to_string())
+            .or_default()
+            .last_day = day;
+        self.save()
+    }
+}
+
+/// Failing for a user doesn't stop the others, and it's tried again on the next run.
+pub async fn send_due<B: JournalBackend>(bot: &Bot, journals: &mut Journals<B>) {
+    let now = Utc::now();
+    for username in journals.usernames() {
+        if let Err(e) = remind(bot, journals, &username, now).await {
+            log::error!("Failed to remind {}: {:?}", username, e);
+        }
+    }
+}
+
+async fn remind<B: JournalBackend>(
+    bot: &Bot,
+    journals: &mut Journals<B>,
+    username: &str,
+    now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let Some(at) = journals.settings.reminder(username) else {
+        return Ok(());
+    };
+    let day_start_hour = journals.settings.day_start_hour(username);
+    let (date, time) = Command::fix_date(now, journals.settings.timezone(username), day_start_hour);
+    if journals.reminders.last_day(username) == Some(date) || !is_due(at, time, day_start_hour) {
+        return Ok(());
+    }
+    // In private chats, the chat id is the user id.
+    let chat_id = journals
+        .reminders
+        .chat(username)
+        .or_else(|| Some(ChatId(journals.telegram_id(username)? as i64)))
+        .context("No chat to remind in, set the reminder with /reminder")?;
+    let journal = journals.user(username)?;
+    journal.check_can_access().await?;
+    if !journal.moods(date, date).await?.is_empty() {
+        log::info!("Not reminding {}, {} has a mood already", username, date);
+        return journals.reminders.set_last_day(username, Some(date));
+    }
+    // Recorded before sending, so if it can't be saved the user isn't asked on every run.
+    let previous = journals.reminders.last_day(username);
+    journals.reminders.set_last_day(username, Some(date))?;
+    log::info!("Reminding {} of the mood of {}", username, date);
+    if let Err(e) = ask(bot, chat_id, date).await {
+        // Try again on the next run.
+        if let Err(e) = journals.reminders.set_last_day(username, previous) {
+            log::error!("Failed to forget reminder of {}: {:?}", username, e);
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+pub async fn ask(bot: &Bot, chat_id: ChatId, date: NaiveDate) -> anyhow::Result<()> {
+    let buttons = MOODS.iter().map(|(emoji, mood)| {
+        InlineKeyboardButton::callback(
+            format!("{} {}", emoji, mood),
+            format!("{}{} {}", CALLBACK_PREFIX, date, mood),
+        )
+    });
+    bot.send_message(
+        chat_id,
+        "How is your day going? Pick a mood, or send /mood with any number from 0 to 100.",
+    )
+    .reply_markup(InlineKeyboardMarkup::new([buttons]))
+    .await?;
+    Ok(())
+}
+
+pub fn is_mood_callback(query: &CallbackQuery) -> bool {
+    query
+        .data
+        .as_deref()
+        .is_some_and(|data| data.starts_with(CALLBACK_PREFIX))
+}
+
+/// Turns the chosen mood into a command for the day it was asked about.
+pub async fn handle_callback(
+    bot: &Bot,
+    query: CallbackQuery,
+    username: String,
+    update_id: u32,
+) -> anyhow::Result<IncomingMessage> {
+    disambiguation::answer_or_log(bot, &query).await;
+    let message = query
+        .message
+        .as_ref()
+        .and_then(|m| m.regular_message())
+        .ok_or_else(|| anyhow::anyhow!("Keyboard message is too old"))?;
+    let (date, mood) = query
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix(CALLBACK_PREFIX)?.split_once(' '))
+        .ok_or_else(|| anyhow::anyhow!("Invalid callback data: {:?}", query.data))?;
+    let day: NaiveDate = date.parse()?;
+    let mood: u8 = mood.parse()?;
+    // /mood still changes it later.
+    disambiguation::edit_or_log(bot, message, format!("Mood: {}", mood)).await;
+    Ok(IncomingMessage {
+        content: Content::Command(Command::Mood(mood)),
+        username,
+        date: Utc::now(),
+        chat_id: message.chat.id,
+        day: Some(day),
+        update_id,
+        message_id: None,
+        formatting: vec![],
+        edited: false,
+    })
+}
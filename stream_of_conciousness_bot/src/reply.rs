@@ -0,0 +1,227 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use teloxide::{
+    prelude::*,
+    types::{ChatId, InputFile},
+};
+
+use crate::{disambiguation, journal::AddedPeople};
+
+/// What happened with the messages of a chat in a batch, so users notice problems right away.
+#[derive(Default, Debug)]
+pub struct Reply {
+    mood: Option<u8>,
+    entries: usize,
+    edited: usize,
+    files: usize,
+    people: AddedPeople,
+    removed: Vec<String>,
+    /// We ask about these with a keyboard.
+    ambiguous: Vec<(NaiveDate, String, Vec<String>)>,
+    settings: Vec<String>,
+    undone: Vec<String>,
+    ignored: Vec<String>,
+    failures: Vec<String>,
+    /// Sent on their own, like a day or search results.
+    shown: Vec<String>,
+    /// PNGs with their caption, like the `/stats` chart.
+    photos: Vec<(Vec<u8>, String)>,
+}
+
+impl Reply {
+    pub fn mood(&mut self, mood: u8) {
+        self.mood = Some(mood);
+    }
+
+    pub fn entries(&mut self, count: usize) {
+        self.entries += count;
+    }
+
+    pub fn edited(&mut self) {
+        self.edited += 1;
+    }
+
+    pub fn file(&mut self) {
+        self.files += 1;
+    }
+
+    pub fn people(&mut self, date: NaiveDate, mut people: AddedPeople) {
+        self.ambiguous.extend(
+            people
+                .ambiguous
+                .drain(..)
+                .map(|(name, candidates)| (date, name, candidates)),
+        );
+        self.people.extend(people);
+    }
+
+    pub fn removed(&mut self, people: Vec<String>) {
+        self.removed.extend(people);
+    }
+
+    pub fn setting(&mut self, description: String) {
+        self.settings.push(description);
+    }
+
+    pub fn undone(&mut self, what: String) {
+        self.undone.push(what);
+    }
+
+    pub fn ignored(&mut self, what: String) {
+        self.ignored.push(what);
+    }
+
+    pub fn show(&mut self, text: String) {
+        self.shown.push(text);
+    }
+
+    pub fn photo(&mut self, png: Vec<u8>, caption: String) {
+        self.photos.push((png, caption));
+    }
+
+    pub fn failure(&mut self, what: String, error: &anyhow::Error) {
+        self.failures.push(format!("{}: {}", what, error));
+    }
+
+    pub fn gave_up(&mut self, what: String, attempts: u32, error: &str) {
+        self.failures.push(format!(
+            "{} after {} attempts, giving up: {}",
+            what, attempts, error
+        ));
+    }
+
+    pub fn merge(&mut self, other: Self) {
+        self.mood = other.mood.or(self.mood);
+        self.entries += other.entries;
+        self.edited += other.edited;
+        self.files += other.files;
+        self.people.extend(other.people);
+        self.removed.extend(other.removed);
+        self.ambiguous.extend(other.ambiguous);
+        self.settings.extend(other.settings);
+        self.undone.extend(other.undone);
+        self.ignored.extend(other.ignored);
+        self.failures.extend(other.failures);
+        self.shown.extend(other.shown);
+        self.photos.extend(other.photos);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.to_string().is_empty()
+    }
+
+    /// Messages to send, longer ones split to fit Telegram.
+    fn messages(&self) -> Vec<String> {
+        let summary = (!self.is_empty()).then(|| self.to_string());
+        summary
+            .iter()
+            .chain(&self.shown)
+            .flat_map(|text| split_message(text, MAX_MESSAGE_LENGTH))
+            .collect()
+    }
+}
+
+impl std::fmt::Display for Reply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(mood) = self.mood {
+            writeln!(f, "✅ Mood set to {}", mood)?;
+        }
+        if self.entries > 0 {
+            writeln!(f, "✅ {} entries added", self.entries)?;
+        }
+        if self.edited > 0 {
+            writeln!(f, "✅ {} entries edited", self.edited)?;
+        }
+        if self.files > 0 {
+            writeln!(f, "✅ {} files added", self.files)?;
+        }
+        if !self.people.added.is_empty() {
+            writeln!(f, "✅ People added: {}", self.people.added.join(", "))?;
+        }
+        if !self.removed.is_empty() {
+            writeln!(f, "✅ People removed: {}", self.removed.join(", "))?;
+        }
+        for setting in &self.settings {
+            writeln!(f, "✅ {}", setting)?;
+        }
+        for undone in &self.undone {
+            writeln!(f, "↩️ Undone: {}", undone)?;
+        }
+        if !self.people.not_found.is_empty() {
+            writeln!(f, "⚠️ Not found: {}", self.people.not_found.join(", "))?;
+        }
+        for (_, name, candidates) in &self.ambiguous {
+            writeln!(f, "⚠️ Ambiguous: {} ({})", name, candidates.join(", "))?;
+        }
+        for ignored in &self.ignored {
+            writeln!(f, "⚠️ Ignored {}", ignored)?;
+        }
+        for failure in &self.failures {
+            writeln!(f, "❌ Failed {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+/// Telegram's limit, in characters.
+const MAX_MESSAGE_LENGTH: usize = 4096;
+
+/// At line breaks when possible.
+pub fn split_message(text: &str, max: usize) -> Vec<String> {
+    let mut messages = vec![];
+    let mut current = String::new();
+    let mut current_len = 0;
+    for line in text.split_inclusive('\n') {
+        let mut line = line;
+        let mut len = line.chars().count();
+        if current_len + len > max && !current.is_empty() {
+            messages.push(std::mem::take(&mut current));
+            current_len = 0;
+        }
+        while len > max {
+            let at = line.char_indices().nth(max).map_or(line.len(), |(i, _)| i);
+            messages.push(line[..at].to_string());
+            line = &line[at..];
+            len -= max;
+        }
+        current.push_str(line);
+        current_len += len;
+    }
+    if !current.trim().is_empty() {
+        messages.push(current);
+    }
+    messages
+}
+
+/// One reply per chat.
+#[derive(Default)]
+pub struct Replies(BTreeMap<ChatId, Reply>);
+
+impl Replies {
+    pub fn chat(&mut self, chat_id: ChatId) -> &mut Reply {
+        self.0.entry(chat_id).or_default()
+    }
+
+    /// Failing to reply is not a reason to fail the batch, the journal is already written.
+    pub async fn send(self, bot: &Bot) {
+        for (chat_id, reply) in self.0 {
+            for message in reply.messages() {
+                if let Err(e) = bot.send_message(chat_id, message).await {
+                    log::error!("Failed to reply to {}: {:?}", chat_id, e);
+                }
+            }
+            for (png, caption) in reply.photos.iter().cloned() {
+                let photo = InputFile::memory(png).file_name("mood.png");
+                if let Err(e) = bot.send_photo(chat_id, photo).caption(caption).await {
+                    log::error!("Failed to send photo to {}: {:?}", chat_id, e);
+                }
+            }
+            for (date, name, candidates) in &reply.ambiguous {
+                if let Err(e) = disambiguation::ask(bot, chat_id, *date, name, candidates).await {
+                    log::error!("Failed to ask about {} to {}: {:?}", name, chat_id, e);
+                }
+            }
+        }
+    }
+}
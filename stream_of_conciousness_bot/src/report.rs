@@ -0,0 +1,85 @@
+//! What a run did, per user. Returned by [`crate::poll_once_and_update_notion`], so the Lambda
+//! can return it as JSON.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub struct PollReport {
+    pub users: BTreeMap<String, UserReport>,
+    /// Updates from people that aren't configured, by their Telegram username (or id).
+    pub unknown_users: BTreeMap<String, usize>,
+    /// Everything failed, so Notion is likely down. The updates weren't acked, and Telegram
+    /// sends them again next time.
+    pub all_failed: bool,
+}
+
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub struct UserReport {
+    /// Telegram updates, before merging them into commands.
+    pub updates: usize,
+    /// After merging, including settings.
+    pub commands: usize,
+    pub successes: usize,
+    /// Why each command failed.
+    pub failures: Vec<String>,
+    /// Messages that aren't text or a file, like stickers, and commands ignored, like ones with
+    /// a bad argument or edits that can't be followed.
+    pub skipped: usize,
+}
+
+impl UserReport {
+    pub fn success(&mut self) {
+        self.commands += 1;
+        self.successes += 1;
+    }
+
+    pub fn failure(&mut self, what: impl std::fmt::Display, error: &anyhow::Error) {
+        self.commands += 1;
+        self.failures.push(format!("{}: {:#}", what, error));
+    }
+}
+
+impl PollReport {
+    pub fn user(&mut self, username: &str) -> &mut UserReport {
+        self.users.entry(username.to_string()).or_default()
+    }
+
+    pub fn unknown_user(&mut self, name: String) {
+        *self.unknown_users.entry(name).or_default() += 1;
+    }
+
+    pub fn commands(&self) -> usize {
+        self.users.values().map(|user| user.commands).sum()
+    }
+
+    pub fn successes(&self) -> usize {
+        self.users.values().map(|user| user.successes).sum()
+    }
+
+    /// With commands, as a batch with only stickers or ignored edits has nothing to retry.
+    pub fn none_succeeded(&self) -> bool {
+        self.successes() == 0 && self.commands() > 0
+    }
+
+    pub fn has_failures(&self) -> bool {
+        self.all_failed || self.successes() < self.commands()
+    }
+
+    /// Of a later batch in the same run.
+    pub fn merge(&mut self, other: Self) {
+        for (username, other) in other.users {
+            let user = self.user(&username);
+            user.updates += other.updates;
+            user.commands += other.commands;
+            user.successes += other.successes;
+            user.failures.extend(other.failures);
+            user.skipped += other.skipped;
+        }
+        for (name, count) in other.unknown_users {
+            *self.unknown_users.entry(name).or_default() += count;
+        }
+        self.all_failed |= other.all_failed;
+    }
+}
@@ -0,0 +1,199 @@
+//! Notion fails for a while sometimes (rate limits, deploys), so calls are retried, and paced
+//! so a big backlog doesn't hit the rate limit in the first place.
+
+use std::time::Duration;
+
+use notion_client::NotionClientError;
+use tokio::time::Instant;
+
+/// How long Notion asked us to wait, from the `Retry-After` header. notion-client drops the
+/// headers, which is why we send the requests ourselves.
+#[derive(Debug)]
+pub struct RetryAfter {
+    pub delay: Duration,
+    pub error: NotionClientError,
+}
+
+impl std::fmt::Display for RetryAfter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (retry after {:?})", self.error, self.delay)
+    }
+}
+
+impl std::error::Error for RetryAfter {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// A token bucket. Notion allows an average of 3 requests per second per integration, with
+/// some bursts.
+pub struct RequestBudget {
+    per_second: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RequestBudget {
+    pub fn new(per_second: f64, burst: u32) -> Self {
+        Self {
+            per_second,
+            burst: burst.into(),
+            tokens: burst.into(),
+            last: Instant::now(),
+        }
+    }
+
+    /// Waits until a request can be made.
+    pub async fn acquire(&mut self) {
+        let now = Instant::now();
+        self.tokens =
+            (self.tokens + (now - self.last).as_secs_f64() * self.per_second).min(self.burst);
+        self.last = now;
+        if self.tokens < 1.0 {
+            let wait = Duration::from_secs_f64((1.0 - self.tokens) / self.per_second);
+            tokio::time::sleep(wait).await;
+            self.tokens = 1.0;
+            self.last = Instant::now();
+        }
+        self.tokens -= 1.0;
+    }
+}
+
+impl Default for RequestBudget {
+    fn default() -> Self {
+        Self::new(3.0, 10)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Counting the first one.
+    pub max_attempts: u32,
+    /// Doubles after each attempt, plus up to half of it of jitter.
+    pub base_delay: Duration,
+    /// Also for what `Retry-After` asks.
+    pub max_delay: Duration,
+    /// Of all the attempts together, so a run doesn't outlive the Lambda's timeout.
+    pub max_elapsed: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            max_elapsed: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2_u32.saturating_pow(attempt - 1))
+            .min(self.max_delay);
+        delay + delay.mul_f64(fastrand::f64() / 2.0)
+    }
+}
+
+enum Verdict {
+    /// Notion didn't do anything, always safe to retry.
+    NotApplied,
+    /// Like a 502, it might have worked, only retried if doing it twice is fine.
+    MaybeApplied,
+    Fatal,
+}
+
+fn request_verdict(error: &reqwest::Error) -> Verdict {
+    if error.is_connect() {
+        Verdict::NotApplied
+    } else {
+        Verdict::MaybeApplied
+    }
+}
+
+/// A `Retry-After` only says when to try again, not whether it worked.
+fn classify(error: &anyhow::Error) -> Verdict {
+    if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+        return request_verdict(error);
+    }
+    let error = match error.downcast_ref::<RetryAfter>() {
+        Some(retry_after) => Some(&retry_after.error),
+        None => error.downcast_ref(),
+    };
+    match error {
+        Some(NotionClientError::InvalidStatusCode { error }) => match error.status {
+            // Rate limited, Notion refused it right away.
+            429 => Verdict::NotApplied,
+            // Conflicting with another request, and unavailable, which Notion also uses for
+            // its own timeouts.
+            409 | 500 | 502 | 503 | 504 => Verdict::MaybeApplied,
+            _ => Verdict::Fatal,
+        },
+        Some(
+            NotionClientError::FailedToRequest { source }
+            | NotionClientError::FailedToText { source },
+        ) => request_verdict(source),
+        // Proxies in front of Notion answer errors with HTML.
+        Some(NotionClientError::FailedToDeserialize { body, .. })
+            if !body.trim_start().starts_with('{') =>
+        {
+            Verdict::MaybeApplied
+        }
+        _ => Verdict::Fatal,
+    }
+}
+
+#[derive(Default)]
+pub struct Retrier {
+    pub policy: RetryPolicy,
+    pub budget: RequestBudget,
+}
+
+impl Retrier {
+    /// Calls that are `idempotent` are also retried when they might have worked.
+    pub async fn run<T, E: Into<anyhow::Error>>(
+        &mut self,
+        what: &str,
+        idempotent: bool,
+        mut call: impl AsyncFnMut() -> Result<T, E>,
+    ) -> anyhow::Result<T> {
+        let started = Instant::now();
+        let mut attempt = 1;
+        loop {
+            self.budget.acquire().await;
+            let error = match call().await {
+                Ok(result) => return Ok(result),
+                Err(error) => error.into(),
+            };
+            match classify(&error) {
+                Verdict::NotApplied => {}
+                Verdict::MaybeApplied if idempotent => {}
+                Verdict::MaybeApplied | Verdict::Fatal => return Err(error),
+            }
+            let remaining = self.policy.max_elapsed.saturating_sub(started.elapsed());
+            if attempt >= self.policy.max_attempts || remaining.is_zero() {
+                return Err(error.context(format!("Gave up after {} attempts", attempt)));
+            }
+            let delay = match error.downcast_ref::<RetryAfter>() {
+                Some(retry_after) => retry_after.delay,
+                None => self.policy.backoff(attempt),
+            }
+            .min(self.policy.max_delay)
+            .min(remaining);
+            log::warn!(
+                "Notion {} failed (attempt {}), retrying in {:?}: {:#}",
+                what,
+                attempt,
+                delay,
+                error
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Finding past entries with `/search`, the same way for every backend.
+
+use chrono::{Days, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::journal::normalize;
+
+/// More than fits in a chat anyway.
+pub const MAX_HITS: usize = 10;
+/// Without `since:`, as each day is a request in Notion. About 10 seconds.
+pub const DEFAULT_DAYS: u64 = 31;
+/// Even with `since:`, so searches don't take forever. About a year of entries.
+pub const MAX_DAYS: usize = 366;
+/// Characters around the first term in a snippet.
+const BEFORE: usize = 60;
+const AFTER: usize = 140;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Search {
+    /// Normalized, all must be in the same line.
+    pub terms: Vec<String>,
+    pub since: Option<NaiveDate>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hit {
+    pub date: NaiveDate,
+    pub snippet: String,
+    /// Of the day's page, if the backend has one.
+    pub url: Option<String>,
+}
+
+impl Search {
+    /// Like `coffee ana since:2026-01-01`.
+    pub fn parse(query: &str) -> Result<Self, String> {
+        let mut terms = vec![];
+        let mut since = None;
+        for word in query.split_whitespace() {
+            if let Some(date) = word.strip_prefix("since:") {
+                since = Some(
+                    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                        .map_err(|_| format!("{}, use since:2026-10-01", word))?,
+                );
+            } else {
+                // Like emojis, which would match anything.
+                let term = normalize(word);
+                if !term.is_empty() {
+                    terms.push(term);
+                }
+            }
+        }
+        if terms.is_empty() {
+            return Err("/search without terms, use /search coffee since:2026-10-01".to_string());
+        }
+        Ok(Self { terms, since })
+    }
+
+    /// Without `since:`, only the last [`DEFAULT_DAYS`] until `today`.
+    pub fn with_default_since(&self, today: NaiveDate) -> Self {
+        Self {
+            since: Some(self.since.unwrap_or(today - Days::new(DEFAULT_DAYS - 1))),
+            ..self.clone()
+        }
+    }
+
+    /// The snippets of the lines with all the terms.
+    pub fn find<'a>(&'a self, lines: &'a [String]) -> impl Iterator<Item = String> + 'a {
+        lines.iter().filter_map(|line| self.snippet(line))
+    }
+
+    /// Normalized char by char, so positions there map back to the line.
+    fn snippet(&self, line: &str) -> Option<String> {
+        let chars: Vec<char> = line.chars().collect();
+        let mut normalized = String::new();
+        let mut origin = vec![];
+        for (i, c) in chars.iter().enumerate() {
+            let n = normalize(c.encode_utf8(&mut [0; 4]));
+            origin.extend(std::iter::repeat_n(i, n.len()));
+            normalized.push_str(&n);
+        }
+        let first = self
+            .terms
+            .iter()
+            .map(|term| normalized.find(term.as_str()))
+            .collect::<Option<Vec<_>>>()?
+            .into_iter()
+            .min()?;
+        let at = origin.get(first).copied().unwrap_or(0);
+        let start = at.saturating_sub(BEFORE);
+        let end = (at + AFTER).min(chars.len());
+        let mut snippet: String = chars[start..end].iter().collect();
+        if start > 0 {
+            snippet.insert(0, '…');
+        }
+        if end < chars.len() {
+            snippet.push('…');
+        }
+        Some(snippet)
+    }
+}
+
+impl std::fmt::Display for Hit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.url {
+            Some(url) => writeln!(f, "📅 {} {}", self.date, url)?,
+            None => writeln!(f, "📅 {}", self.date)?,
+        }
+        writeln!(f, "{}", self.snippet)
+    }
+}
+
+/// All in one message, split later if too long. `search` is as the user wrote it.
+pub fn results(search: &Search, hits: &[Hit]) -> String {
+    let terms = search.terms.join(" ");
+    let mut text = if hits.is_empty() {
+        format!("🔎 Nothing found for {}\n", terms)
+    } else {
+        format!("🔎 {} for {}\n", hits.len(), terms)
+    };
+    for hit in hits {
+        text.push('\n');
+        text.push_str(&hit.to_string());
+    }
+    if search.since.is_none() {
+        text.push_str(&format!(
+            "\nOnly the last {} days, add since:2026-01-01 to search further back.",
+            DEFAULT_DAYS
+        ));
+    }
+    text.trim_end().to_string()
+}
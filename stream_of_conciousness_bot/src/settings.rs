@@ -0,0 +1,184 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use chrono::NaiveTime;
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    journal::{Aliases, normalize_name},
+    reminders::Reminder,
+    state_file,
+};
+
+/// Where I live, used if nothing else is configured.
+pub const DEFAULT_TIMEZONE: Tz = chrono_tz::America::Sao_Paulo;
+/// By default, the day actually changes at 6am.
+pub const DEFAULT_DAY_START_HOUR: u32 = 6;
+
+/// A setting that doesn't make sense, like an unknown timezone. The user's mistake, not ours.
+#[derive(Debug)]
+pub struct InvalidSetting(pub String);
+
+impl std::fmt::Display for InvalidSetting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for InvalidSetting {}
+
+/// Unset fields fall back to the configured value, then to the default.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct UserSettings {
+    pub timezone: Option<Tz>,
+    /// Messages before this hour go to the previous day.
+    pub day_start_hour: Option<u32>,
+    /// Changed ones override the configured ones, and an empty name removes the alias.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, String>,
+    /// When to ask for the mood, see [`crate::reminders`].
+    pub reminder: Option<Reminder>,
+}
+
+pub struct Settings {
+    path: PathBuf,
+    /// From the config, never written.
+    configured: BTreeMap<String, UserSettings>,
+    /// Changed through bot commands, saved to `path`.
+    changed: BTreeMap<String, UserSettings>,
+}
+
+/// Directory where the bot keeps what it changes by itself.
+pub fn state_dir() -> PathBuf {
+    PathBuf::from(std::env::var("STATE_DIR").unwrap_or_else(|_| "state".to_string()))
+}
+
+/// Everything the bot remembers is there, so it's forgotten (and written twice, or reminded
+/// again) on the next run if it can't be saved. Like on Lambda, where only /tmp is writable.
+/// Still runs, each save logs its own error.
+pub fn warn_if_state_dir_not_writable() {
+    let dir = state_dir();
+    let probe = dir.join(".writable");
+    if let Err(e) = std::fs::create_dir_all(&dir)
+        .and_then(|()| std::fs::write(&probe, b""))
+        .and_then(|()| std::fs::remove_file(&probe))
+    {
+        log::warn!("STATE_DIR {} is not writable: {}", dir.display(), e);
+    }
+}
+
+impl Settings {
+    /// The configured ones, and the changed ones from `settings.json` in the state directory.
+    pub fn load(config: &Config, state_dir: &Path) -> anyhow::Result<Self> {
+        let configured = config
+            .users
+            .iter()
+            .map(|user| {
+                (
+                    user.username.clone(),
+                    UserSettings {
+                        timezone: user.timezone,
+                        day_start_hour: user.day_start_hour,
+                        aliases: user.aliases.clone(),
+                        reminder: user.reminder,
+                    },
+                )
+            })
+            .collect();
+        let path = state_dir.join("settings.json");
+        let changed = state_file::read(&path)?;
+        Ok(Self {
+            path,
+            configured,
+            changed,
+        })
+    }
+
+    fn get<T>(&self, username: &str, field: impl Fn(&UserSettings) -> Option<T>) -> Option<T> {
+        self.changed
+            .get(username)
+            .and_then(&field)
+            .or_else(|| self.configured.get(username).and_then(&field))
+    }
+
+    pub fn timezone(&self, username: &str) -> Tz {
+        self.get(username, |s| s.timezone)
+            .unwrap_or(DEFAULT_TIMEZONE)
+    }
+
+    pub fn set_timezone(&mut self, username: &str, timezone: &str) -> anyhow::Result<()> {
+        let timezone = parse_timezone(timezone)?;
+        self.update(username, |s| s.timezone = Some(timezone))
+    }
+
+    pub fn day_start_hour(&self, username: &str) -> u32 {
+        self.get(username, |s| s.day_start_hour)
+            .unwrap_or(DEFAULT_DAY_START_HOUR)
+    }
+
+    pub fn set_day_start_hour(&mut self, username: &str, hour: u32) -> anyhow::Result<()> {
+        let hour = parse_day_start_hour(hour)?;
+        self.update(username, |s| s.day_start_hour = Some(hour))
+    }
+
+    /// `None` when off.
+    pub fn reminder(&self, username: &str) -> Option<NaiveTime> {
+        match self.get(username, |s| s.reminder)? {
+            Reminder::At(time) => Some(time),
+            Reminder::Off => None,
+        }
+    }
+
+    pub fn set_reminder(&mut self, username: &str, reminder: &str) -> anyhow::Result<()> {
+        let reminder = reminder
+            .parse()
+            .map_err(|e| InvalidSetting(format!("{:#}", e)))?;
+        self.update(username, |s| s.reminder = Some(reminder))
+    }
+
+    pub fn aliases(&self, username: &str) -> Aliases {
+        [&self.configured, &self.changed]
+            .into_iter()
+            .filter_map(|settings| settings.get(username))
+            .flat_map(|s| &s.aliases)
+            .map(|(alias, person)| (normalize_name(alias), person.trim().to_string()))
+            .collect::<Aliases>()
+            .into_iter()
+            .filter(|(_, person)| !person.is_empty())
+            .collect()
+    }
+
+    /// An empty `person` removes the alias.
+    pub fn set_alias(&mut self, username: &str, alias: &str, person: &str) -> anyhow::Result<()> {
+        let alias = alias.trim();
+        anyhow::ensure!(!alias.is_empty(), InvalidSetting("Empty alias".to_string()));
+        self.update(username, |s| {
+            s.aliases
+                .insert(alias.to_string(), person.trim().to_string());
+        })
+    }
+
+    fn update(&mut self, username: &str, f: impl FnOnce(&mut UserSettings)) -> anyhow::Result<()> {
+        f(self.changed.entry(username.to_string()).or_default());
+        state_file::write(&self.path, &self.changed)
+    }
+}
+
+fn parse_timezone(timezone: &str) -> anyhow::Result<Tz> {
+    timezone
+        .trim()
+        .parse()
+        .map_err(|e| InvalidSetting(format!("Invalid timezone {:?}: {}", timezone, e)).into())
+}
+
+fn parse_day_start_hour(hour: u32) -> anyhow::Result<u32> {
+    anyhow::ensure!(
+        hour < 24,
+        InvalidSetting(format!("Invalid day start hour: {}", hour))
+    );
+    Ok(hour)
+}
@@ -0,0 +1,30 @@
+//! The JSON files the bot keeps what it remembers in, read and written whole.
+
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{Serialize, de::DeserializeOwned};
+
+/// The default (nothing remembered) if it doesn't exist yet.
+pub fn read<T: DeserializeOwned + Default>(path: &Path) -> anyhow::Result<T> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&json).with_context(|| format!("Invalid file {}", path.display()))
+}
+
+/// Writes to a temporary file first, so a crash never leaves a half written one.
+pub fn write<T: Serialize>(path: &Path, data: &T) -> anyhow::Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    let write = || -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&tmp, serde_json::to_string_pretty(data)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    };
+    write().with_context(|| format!("Failed to save {}", path.display()))
+}
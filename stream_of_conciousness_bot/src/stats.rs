@@ -0,0 +1,171 @@
+//! Mood over time for `/stats`, as numbers and a chart.
+
+use anyhow::Context;
+use chrono::{Datelike, Days, NaiveDate, Weekday};
+use plotters::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 400;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Period {
+    Week,
+    Month,
+    Year,
+}
+
+impl Period {
+    /// A month without anything.
+    pub fn parse(period: &str) -> Result<Self, String> {
+        match period.trim().to_lowercase().as_str() {
+            "week" => Ok(Self::Week),
+            "" | "month" => Ok(Self::Month),
+            "year" => Ok(Self::Year),
+            other => Err(format!("/stats {}, use week, month or year", other)),
+        }
+    }
+
+    /// The days in the period ending on `last`, both included.
+    pub fn range(self, last: NaiveDate) -> (NaiveDate, NaiveDate) {
+        let days = match self {
+            Self::Week => 7,
+            Self::Month => 30,
+            Self::Year => 365,
+        };
+        let first = last.checked_sub_days(Days::new(days - 1)).unwrap_or(last);
+        (first, last)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Week => "week",
+            Self::Month => "30 days",
+            Self::Year => "year",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MoodStats {
+    pub period: Period,
+    pub days: usize,
+    pub mean: f64,
+    pub min: (NaiveDate, u8),
+    pub max: (NaiveDate, u8),
+    /// Change per week, from a least squares fit.
+    pub trend: f64,
+    /// Mean of each weekday with a mood, Monday first.
+    pub weekdays: Vec<(Weekday, f64)>,
+}
+
+impl MoodStats {
+    /// `None` without moods, there's nothing to say.
+    pub fn compute(period: Period, moods: &[(NaiveDate, u8)]) -> Option<Self> {
+        let first = moods.iter().map(|(date, _)| *date).min()?;
+        let min = *moods.iter().min_by_key(|(_, mood)| *mood)?;
+        let max = *moods.iter().max_by_key(|(_, mood)| *mood)?;
+        let n = moods.len() as f64;
+        let mean = moods.iter().map(|(_, mood)| *mood as f64).sum::<f64>() / n;
+        let x = |date: &NaiveDate| (*date - first).num_days() as f64;
+        let mean_x = moods.iter().map(|(date, _)| x(date)).sum::<f64>() / n;
+        let (mut cov, mut var) = (0.0, 0.0);
+        for (date, mood) in moods {
+            cov += (x(date) - mean_x) * (*mood as f64 - mean);
+            var += (x(date) - mean_x).powi(2);
+        }
+        let trend = if var > 0.0 { cov / var * 7.0 } else { 0.0 };
+        let weekdays = std::iter::successors(Some(Weekday::Mon), |day| Some(day.succ()))
+            .take(7)
+            .filter_map(|weekday| {
+                let of_day: Vec<f64> = moods
+                    .iter()
+                    .filter(|(date, _)| date.weekday() == weekday)
+                    .map(|(_, mood)| *mood as f64)
+                    .collect();
+                (!of_day.is_empty())
+                    .then(|| (weekday, of_day.iter().sum::<f64>() / of_day.len() as f64))
+            })
+            .collect();
+        Some(Self {
+            period,
+            days: moods.len(),
+            mean,
+            min,
+            max,
+            trend,
+            weekdays,
+        })
+    }
+}
+
+/// Short enough for a photo caption.
+impl std::fmt::Display for MoodStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "📈 Mood in the last {} ({} days with it)",
+            self.period.name(),
+            self.days
+        )?;
+        writeln!(f, "Mean: {:.1}", self.mean)?;
+        writeln!(f, "Min: {} on {}", self.min.1, self.min.0)?;
+        writeln!(f, "Max: {} on {}", self.max.1, self.max.0)?;
+        let arrow = match self.trend {
+            t if t > 1.0 => "↗️",
+            t if t < -1.0 => "↘️",
+            _ => "➡️",
+        };
+        writeln!(f, "Trend: {:+.1} per week {}", self.trend, arrow)?;
+        let weekdays: Vec<_> = self
+            .weekdays
+            .iter()
+            .map(|(weekday, mean)| format!("{} {:.0}", weekday, mean))
+            .collect();
+        writeln!(f, "{}", weekdays.join(" · "))
+    }
+}
+
+/// A PNG with the moods as a line over the days, without any text: the numbers go in the
+/// caption. Guides at every 25, and a vertical line at each week (or month, for a year).
+pub fn chart(
+    period: Period,
+    (first, last): (NaiveDate, NaiveDate),
+    moods: &[(NaiveDate, u8)],
+) -> anyhow::Result<Vec<u8>> {
+    let mut rgb = vec![0; (WIDTH * HEIGHT * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut rgb, (WIDTH, HEIGHT)).into_drawing_area();
+        root.fill(&WHITE)?;
+        let x = |date: NaiveDate| (date - first).num_days() as f64;
+        let mut chart = ChartBuilder::on(&root)
+            .margin(20)
+            .build_cartesian_2d(0.0..x(last).max(1.0), 0.0..100.0)?;
+        let guide = RGBColor(220, 220, 220);
+        for y in [0.0, 25.0, 50.0, 75.0, 100.0] {
+            chart.draw_series(LineSeries::new([(0.0, y), (x(last), y)], guide))?;
+        }
+        for date in first.iter_days().take_while(|date| *date <= last) {
+            let starts = match period {
+                Period::Year => date.day() == 1,
+                _ => date.weekday() == Weekday::Mon,
+            };
+            if starts {
+                chart.draw_series(LineSeries::new([(x(date), 0.0), (x(date), 100.0)], guide))?;
+            }
+        }
+        let points = moods.iter().map(|(date, mood)| (x(*date), *mood as f64));
+        chart.draw_series(LineSeries::new(points.clone(), BLUE.stroke_width(3)))?;
+        chart.draw_series(points.map(|point| Circle::new(point, 4, BLUE.filled())))?;
+        root.present()?;
+    }
+    let mut png = vec![];
+    let mut encoder = png::Encoder::new(&mut png, WIDTH, HEIGHT);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&rgb))
+        .context("Failed to encode the chart")?;
+    Ok(png)
+}
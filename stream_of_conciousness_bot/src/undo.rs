@@ -0,0 +1,134 @@
+//! The last writes of each user, with what is needed to take them back with `/undo`.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::state_file;
+
+/// Nobody undoes more than a handful in a row.
+const KEEP_PER_USER: usize = 50;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Undo {
+    /// What a text or file became, see [`crate::journal::JournalBackend::remove_blocks`].
+    Blocks {
+        ids: Vec<String>,
+        /// Of the message, to forget it in [`crate::messages`] too.
+        marker: Option<String>,
+    },
+    /// The mood before, if any.
+    Mood(Option<u8>),
+    /// Only the people that weren't there before.
+    People(Vec<String>),
+}
+
+impl std::fmt::Display for Undo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Undo::Blocks { ids, .. } if ids.len() == 1 => write!(f, "entry removed"),
+            Undo::Blocks { ids, .. } => write!(f, "entry removed ({} blocks)", ids.len()),
+            Undo::Mood(Some(mood)) => write!(f, "mood back to {}", mood),
+            Undo::Mood(None) => write!(f, "mood cleared"),
+            Undo::People(people) => write!(f, "people removed: {}", people.join(", ")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Applied {
+    pub date: NaiveDate,
+    pub undo: Undo,
+}
+
+pub struct UndoLog {
+    path: PathBuf,
+    per_user: BTreeMap<String, Vec<Applied>>,
+}
+
+impl UndoLog {
+    /// From `undo.json` in the state directory.
+    pub fn load(state_dir: &Path) -> anyhow::Result<Self> {
+        Self::open(state_dir.join("undo.json"))
+    }
+
+    pub fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let per_user = state_file::read(&path)?;
+        Ok(Self { path, per_user })
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        state_file::write(&self.path, &self.per_user)
+    }
+
+    /// Writes that changed nothing aren't kept, undoing them would look like a no-op.
+    pub fn push(&mut self, username: &str, date: NaiveDate, undo: Undo) -> anyhow::Result<()> {
+        if matches!(&undo, Undo::Blocks { ids, .. } | Undo::People(ids) if ids.is_empty()) {
+            return Ok(());
+        }
+        let applied = self.per_user.entry(username.to_string()).or_default();
+        applied.push(Applied { date, undo });
+        if applied.len() > KEEP_PER_USER {
+            applied.remove(0);
+        }
+        self.save()
+    }
+
+    /// Logs instead of failing, only `/undo` won't take this one back.
+    pub fn push_or_log(&mut self, username: &str, date: NaiveDate, undo: Undo) {
+        if let Err(e) = self.push(username, date, undo) {
+            log::error!("Failed to record undo for {}: {:?}", username, e);
+        }
+    }
+
+    /// Edited texts become other blocks, which are the ones to remove now. A file keeps its
+    /// block, only its caption changes.
+    pub fn edited(
+        &mut self,
+        username: &str,
+        marker: &str,
+        old_ids: &[String],
+        new_ids: &[String],
+    ) -> anyhow::Result<()> {
+        for applied in self.per_user.get_mut(username).into_iter().flatten() {
+            if let Undo::Blocks {
+                ids,
+                marker: Some(m),
+            } = &mut applied.undo
+                && m == marker
+            {
+                ids.retain(|id| !old_ids.contains(id));
+                ids.extend(new_ids.iter().cloned());
+            }
+        }
+        self.save()
+    }
+
+    pub fn edited_or_log(
+        &mut self,
+        username: &str,
+        marker: &str,
+        old_ids: &[String],
+        new_ids: &[String],
+    ) {
+        if let Err(e) = self.edited(username, marker, old_ids, new_ids) {
+            log::error!("Failed to record edit of {} for undo: {:?}", marker, e);
+        }
+    }
+
+    pub fn last(&self, username: &str) -> Option<&Applied> {
+        self.per_user.get(username)?.last()
+    }
+
+    /// Only once it's undone, so a failed undo can be tried again.
+    pub fn pop(&mut self, username: &str) -> anyhow::Result<()> {
+        if let Some(applied) = self.per_user.get_mut(username) {
+            applied.pop();
+        }
+        self.save()
+    }
+}
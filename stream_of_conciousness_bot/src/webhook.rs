@@ -0,0 +1,63 @@
+//! Telegram pushing updates to us, one per request, instead of us polling for them. Used by the
+//! webhook Lambda in `lambda_executor`. Telegram sends the secret given to `setWebhook` in every
+//! request, so anyone else calling the URL is turned away.
+
+use anyhow::Context;
+use teloxide::{prelude::*, types::Update};
+
+use crate::{
+    handle_updates,
+    journal::{JournalBackend, Journals},
+    outbox, reminders,
+    report::PollReport,
+};
+
+pub const SECRET_TOKEN_HEADER: &str = "X-Telegram-Bot-Api-Secret-Token";
+
+/// Compares all the bytes, so the time it takes doesn't tell how much of it was right.
+pub fn verify_secret(expected: &str, given: Option<&str>) -> bool {
+    let Some(given) = given else {
+        return false;
+    };
+    !expected.is_empty()
+        && expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Against TELEGRAM_WEBHOOK_SECRET, without it nothing is authorized.
+pub fn is_authorized(given: Option<&str>) -> bool {
+    match std::env::var("TELEGRAM_WEBHOOK_SECRET") {
+        Ok(expected) => verify_secret(&expected, given),
+        Err(_) => {
+            log::error!("TELEGRAM_WEBHOOK_SECRET is not set, refusing all webhook requests");
+            false
+        }
+    }
+}
+
+pub fn parse_update(body: &[u8]) -> anyhow::Result<Update> {
+    serde_json::from_slice(body).context("Invalid Telegram update")
+}
+
+/// Same as a poll with a single update. If it fails, the request should fail too, so Telegram
+/// sends it again later.
+pub(crate) async fn handle<B: JournalBackend>(
+    bot: &Bot,
+    mut journals: Journals<B>,
+    update: Update,
+) -> anyhow::Result<PollReport> {
+    if let Err(e) = outbox::retry_due(bot, &mut journals).await {
+        log::error!("Failed to retry the outbox: {:?}", e);
+    }
+    reminders::send_due(bot, &mut journals).await;
+    let mut report = handle_updates(bot, &mut journals, vec![update]).await?;
+    if report.none_succeeded() {
+        log::error!("The update failed, Telegram will send it again.");
+        report.all_failed = true;
+    }
+    Ok(report)
+}
@@ -0,0 +1,174 @@
+use chrono::{NaiveDate, Utc};
+use stream_of_conciousness_bot::{
+    commands::{Command, Content, IncomingMessage},
+    config::Config,
+    journal::{JournalBackend, Journals},
+    local_journal::LocalJournal,
+    settings::DEFAULT_TIMEZONE,
+};
+use teloxide::{
+    Bot,
+    types::{ChatId, MessageId},
+};
+use tempfile::TempDir;
+
+/// Nothing listens there, so replies fail right away, which is fine.
+fn bot() -> Bot {
+    Bot::new("123:token").set_api_url(reqwest::Url::parse("http://127.0.0.1:1").unwrap())
+}
+
+/// The state goes next to the journal.
+fn journals(dir: &TempDir) -> Journals<LocalJournal> {
+    let config: Config = toml::from_str("[[users]]\nusername = \"ana\"").unwrap();
+    Journals::open(&config, dir.path(), |user| {
+        LocalJournal::open(dir.path().join(format!("{}.json", user.username)))
+    })
+    .unwrap()
+}
+
+/// Today, as edits are only followed for the last days.
+fn date() -> NaiveDate {
+    Utc::now().date_naive()
+}
+
+/// At noon in the default timezone, each from its own Telegram message.
+fn message(update_id: u32, cmd: Command) -> IncomingMessage {
+    IncomingMessage {
+        content: Content::Command(cmd),
+        username: "ana".to_string(),
+        date: date().and_hms_opt(15, 0, 0).unwrap().and_utc(),
+        chat_id: ChatId(1),
+        day: None,
+        update_id,
+        message_id: Some(MessageId(update_id as i32)),
+        formatting: vec![],
+        edited: false,
+    }
+}
+
+fn text(update_id: u32, text: &str) -> IncomingMessage {
+    message(update_id, Command::Text(text.to_string()))
+}
+
+async fn handle(journals: &mut Journals<LocalJournal>, msgs: Vec<IncomingMessage>) {
+    let handled = Command::handle(&bot(), msgs, journals).await.unwrap();
+    assert!(!handled.report.has_failures());
+    assert!(handled.failed.is_empty());
+}
+
+async fn lines(journals: &mut Journals<LocalJournal>) -> Vec<String> {
+    let day = journals
+        .user("ana")
+        .unwrap()
+        .read_day(date())
+        .await
+        .unwrap();
+    day.map_or(vec![], |day| day.lines)
+}
+
+#[tokio::test]
+async fn merges_a_batch_and_skips_it_when_replayed() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut journals = journals(&dir);
+    let batch = || {
+        vec![
+            text(1, "Hi"),
+            message(2, Command::Mood(70)),
+            text(3, "Bye"),
+            message(4, Command::Mood(40)),
+        ]
+    };
+    handle(&mut journals, batch()).await;
+    // Moods are merged to the lowest.
+    let day = journals
+        .user("ana")
+        .unwrap()
+        .read_day(date())
+        .await
+        .unwrap();
+    assert_eq!(day.unwrap().mood, Some(40));
+    assert_eq!(lines(&mut journals).await, ["[12:00] Hi", "[12:00] Bye"]);
+    assert!((1..=4).all(|id| journals.ledger.is_applied("ana", id)));
+    // Like after a crash before Telegram got the ack.
+    let mut journals = self::journals(&dir);
+    handle(&mut journals, batch()).await;
+    assert_eq!(lines(&mut journals).await, ["[12:00] Hi", "[12:00] Bye"]);
+}
+
+#[tokio::test]
+async fn skips_texts_journaled_before_a_crash() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut journals = journals(&dir);
+    handle(&mut journals, vec![text(1, "Hi")]).await;
+    // Like a crash before the ledger was saved.
+    std::fs::remove_file(dir.path().join("ledger.json")).unwrap();
+    let mut journals = self::journals(&dir);
+    let handled = Command::handle(&bot(), vec![text(1, "Hi")], &mut journals)
+        .await
+        .unwrap();
+    // Not even given to the journal.
+    assert_eq!(handled.report.commands(), 0);
+    assert!(journals.ledger.is_applied("ana", 1));
+    assert_eq!(lines(&mut journals).await, ["[12:00] Hi"]);
+}
+
+#[tokio::test]
+async fn undoes_the_last_write() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut journals = journals(&dir);
+    handle(&mut journals, vec![text(1, "Hi")]).await;
+    handle(&mut journals, vec![text(2, "Bye"), text(3, "Later")]).await;
+    // One message at a time, even when they were written together.
+    handle(&mut journals, vec![message(4, Command::Undo)]).await;
+    assert_eq!(lines(&mut journals).await, ["[12:00] Hi", "[12:00] Bye"]);
+    handle(&mut journals, vec![message(5, Command::Undo)]).await;
+    assert_eq!(lines(&mut journals).await, ["[12:00] Hi"]);
+    handle(&mut journals, vec![message(6, Command::Undo)]).await;
+    assert!(lines(&mut journals).await.is_empty());
+}
+
+#[tokio::test]
+async fn edits_what_the_message_became() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut journals = journals(&dir);
+    handle(&mut journals, vec![text(1, "Hi"), text(2, "Bye")]).await;
+    handle(&mut journals, vec![text(3, "Later")]).await;
+    handle(&mut journals, vec![message(4, Command::Undo)]).await;
+    let edit = IncomingMessage {
+        edited: true,
+        message_id: Some(MessageId(1)),
+        ..text(5, "Hello")
+    };
+    handle(&mut journals, vec![edit]).await;
+    assert_eq!(lines(&mut journals).await, ["[12:00] Hello", "[12:00] Bye"]);
+}
+
+#[tokio::test]
+async fn bad_arguments_are_ignored_not_failures() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut journals = journals(&dir);
+    let msgs = vec![
+        message(1, Command::Timezone("Mars/Olympus".to_string())),
+        message(2, Command::Alias("Bia".to_string())),
+        message(3, Command::Day("yesterday".to_string())),
+    ];
+    let handled = Command::handle(&bot(), msgs, &mut journals).await.unwrap();
+    assert!(!handled.report.has_failures());
+    assert_eq!(handled.report.users["ana"].skipped, 3);
+    // Sending them again wouldn't help.
+    assert!((1..=3).all(|id| journals.ledger.is_applied("ana", id)));
+}
+
+#[test]
+fn fix_date_rolls_over_at_the_day_start() {
+    let fix = |utc: &str, day_start_hour| {
+        let (date, time) =
+            Command::fix_date(utc.parse().unwrap(), DEFAULT_TIMEZONE, day_start_hour);
+        format!("{} {}", date, time.format("%H:%M"))
+    };
+    // São Paulo is 3 hours behind.
+    assert_eq!(fix("2024-10-21T08:59:00Z", 6), "2024-10-20 05:59");
+    assert_eq!(fix("2024-10-21T09:00:00Z", 6), "2024-10-21 06:00");
+    assert_eq!(fix("2024-10-21T02:30:00Z", 0), "2024-10-20 23:30");
+    assert_eq!(fix("2024-10-21T03:00:00Z", 0), "2024-10-21 00:00");
+}
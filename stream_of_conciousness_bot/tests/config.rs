@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use stream_of_conciousness_bot::config::Config;
+
+const DB_ID: &str = "d0000000-0000-4000-8000-000000000000";
+
+fn config(toml: &str, env: &[(&str, &str)]) -> anyhow::Result<Config> {
+    let env: BTreeMap<_, _> = env.iter().copied().collect();
+    toml::from_str::<Config>(toml)?.with_env(|var| env.get(var).map(ToString::to_string))
+}
+
+fn error(toml: &str, env: &[(&str, &str)]) -> String {
+    format!("{:#}", config(toml, env).unwrap_err())
+}
+
+#[test]
+fn env_overrides_and_adds_to_the_file() {
+    let config = config(
+        r#"
+        [[users]]
+        username = "ana"
+        notion_token_file = "token.txt"
+        database_id = "d0000000000040008000000000000000"
+        timezone = "Europe/London"
+        day_start_hour = 4
+        "#,
+        &[
+            ("TELEGRAM_USERNAMES", "ana, bia"),
+            ("NOTION_TOKENS", "secret_a,secret_b"),
+            ("NOTION_DATABASE_IDS", &format!("{},{}", DB_ID, DB_ID)),
+            // Empty keeps what the file has.
+            ("TIMEZONES", ",America/Recife"),
+            ("DAY_START_HOURS", "0,"),
+        ],
+    )
+    .unwrap();
+    let [ana, bia] = &config.users[..] else {
+        panic!("Not two users: {:?}", config.users);
+    };
+    assert_eq!(ana.username, "ana");
+    // Instead of the file.
+    assert_eq!(ana.notion_token().unwrap(), "secret_a");
+    assert_eq!(ana.database_id().unwrap(), DB_ID);
+    assert_eq!(ana.timezone, Some(chrono_tz::Europe::London));
+    assert_eq!(ana.day_start_hour, Some(0));
+    assert_eq!(bia.username, "bia");
+    assert_eq!(bia.notion_token().unwrap(), "secret_b");
+    assert_eq!(bia.timezone, Some(chrono_tz::America::Recife));
+    assert_eq!(bia.day_start_hour, None);
+}
+
+#[test]
+fn per_user_env_needs_a_value_per_user() {
+    let message = error(
+        "",
+        &[
+            ("TELEGRAM_USERNAMES", "ana,bia"),
+            ("NOTION_TOKENS", "secret_a"),
+        ],
+    );
+    assert!(message.contains("NOTION_TOKENS has 1 values, but TELEGRAM_USERNAMES has 2"));
+}
+
+#[test]
+fn rejects_duplicate_users() {
+    let message = error(
+        "[[users]]\nusername = \"ana\"",
+        &[("TELEGRAM_USERNAMES", "bia,Ana")],
+    );
+    // Ana isn't the same username as ana for the override, but is for Telegram.
+    assert!(message.contains("Duplicate username Ana"), "{}", message);
+    let message = error(
+        r#"
+        [[users]]
+        username = "ana"
+        telegram_id = 1234
+        [[users]]
+        username = "bia"
+        telegram_id = 1234
+        "#,
+        &[],
+    );
+    assert!(
+        message.contains("Duplicate telegram_id 1234"),
+        "{}",
+        message
+    );
+}
+
+#[test]
+fn rejects_malformed_database_ids() {
+    for db_id in [
+        "d0000000-0000-4000-8000",
+        "zzzzzzzz-0000-4000-8000-000000000000",
+    ] {
+        let message = error(
+            &format!("[[users]]\nusername = \"ana\"\ndatabase_id = \"{}\"", db_id),
+            &[],
+        );
+        assert!(message.contains("Invalid database_id"), "{}", message);
+    }
+}
+
+#[test]
+fn needs_exactly_one_token() {
+    let config = config(
+        r#"
+        [[users]]
+        username = "ana"
+        notion_token = "secret"
+        notion_token_file = "token.txt"
+        [[users]]
+        username = "bia"
+        "#,
+        &[],
+    )
+    .unwrap();
+    let message = format!("{:#}", config.users[0].notion_token().unwrap_err());
+    assert_eq!(message, "Both notion_token and notion_token_file");
+    let message = format!("{:#}", config.users[1].notion_token().unwrap_err());
+    assert_eq!(message, "No notion_token or notion_token_file");
+}
@@ -0,0 +1,13 @@
+use chrono::NaiveDate;
+use stream_of_conciousness_bot::disambiguation::{callback_data, parse_callback_data};
+
+#[test]
+fn callback_data_round_trips() {
+    let date = NaiveDate::from_ymd_opt(2024, 10, 20).unwrap();
+    let data = callback_data(date, 3);
+    // Telegram only allows 64 bytes.
+    assert!(data.len() <= 64);
+    assert_eq!(parse_callback_data(&data).unwrap(), (date, 3));
+    assert!(parse_callback_data("2024-10-20").is_err());
+    assert!(parse_callback_data("mood 2024-10-20 70").is_err());
+}
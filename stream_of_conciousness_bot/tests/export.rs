@@ -0,0 +1,52 @@
+use chrono::NaiveDate;
+use stream_of_conciousness_bot::{
+    export::{Format, markdown, write},
+    journal::Day,
+};
+
+fn day(d: u32, mood: Option<u8>) -> Day {
+    Day {
+        date: NaiveDate::from_ymd_opt(2024, 10, d).unwrap(),
+        mood,
+        people: vec!["Ana".to_string(), "Bia: \"B\"".to_string()],
+        tags: vec!["Stream of conciousness".to_string()],
+        lines: vec!["[09:00] Coffee".to_string(), "📎 photo.jpg".to_string()],
+    }
+}
+
+#[test]
+fn writes_markdown_with_front_matter() {
+    assert_eq!(
+        markdown(&day(20, Some(70))).unwrap(),
+        "---\n\
+         date: 2024-10-20\n\
+         mood: 70\n\
+         people: [\"Ana\",\"Bia: \\\"B\\\"\"]\n\
+         tags: [\"Stream of conciousness\"]\n\
+         ---\n\
+         \n[09:00] Coffee\n\
+         \n📎 photo.jpg\n"
+    );
+    assert!(!markdown(&day(20, None)).unwrap().contains("mood"));
+}
+
+#[test]
+fn writes_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let days = [day(20, Some(70)), day(21, None)];
+    let path = write(&days, Format::Markdown, dir.path(), "ana").unwrap();
+    assert_eq!(path, dir.path().join("ana"));
+    assert!(path.join("2024-10-20.md").exists());
+    assert!(path.join("2024-10-21.md").exists());
+    let path = write(&days, Format::JsonLines, dir.path(), "ana").unwrap();
+    let lines: Vec<serde_json::Value> = std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["date"], "2024-10-20");
+    assert_eq!(lines[0]["mood"], 70);
+    assert_eq!(lines[1]["mood"], serde_json::Value::Null);
+    assert!("csv".parse::<Format>().is_err());
+}
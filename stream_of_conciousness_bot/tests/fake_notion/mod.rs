@@ -0,0 +1,604 @@
+//! A tiny in-process stand-in for the Notion API, only with the endpoints the bot uses.
+//! It keeps everything as JSON in memory, and tests read it back as notion-client objects.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use hyper::{Body, Method, Request, Response, StatusCode, server::conn::Http, service::service_fn};
+use notion_client::objects::{block::Block, page::Page};
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+
+pub const DATABASE_ID: &str = "d0000000-0000-4000-8000-000000000000";
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    people: Vec<String>,
+    pages: Vec<Value>,
+    /// Children of each block or page, in order.
+    children: BTreeMap<String, Vec<Value>>,
+    uploads: BTreeMap<String, Upload>,
+    /// For the next requests, `None` ones succeed.
+    failures: VecDeque<Option<Failure>>,
+    received: Vec<Received>,
+}
+
+/// A request as it arrived, even if it failed.
+#[derive(Clone, Debug)]
+pub struct Received {
+    pub method: Method,
+    /// Without `/v1/`.
+    pub path: String,
+    pub at: Instant,
+}
+
+/// Makes a request fail, like Notion does when overloaded.
+#[derive(Clone, Copy, Default)]
+pub struct Failure {
+    pub status: u16,
+    /// Sent as the `Retry-After` header, in seconds.
+    pub retry_after: Option<u64>,
+    /// The request still does what it should, only the response is lost.
+    pub after_applying: bool,
+}
+
+#[derive(Clone)]
+pub struct Upload {
+    pub filename: String,
+    pub content_type: String,
+    /// Only after it is sent.
+    pub data: Option<Vec<u8>>,
+}
+
+impl State {
+    fn new_id(&mut self) -> String {
+        self.next_id += 1;
+        format!("{:08x}-0000-4000-8000-000000000000", self.next_id)
+    }
+}
+
+pub struct FakeNotion {
+    addr: std::net::SocketAddr,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeNotion {
+    /// Starts serving a database with the given `Pessoas` options.
+    pub async fn start(people: &[&str]) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State {
+            people: people.iter().map(ToString::to_string).collect(),
+            ..Default::default()
+        }));
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                let (tcp, _) = listener.accept().await.unwrap();
+                let state = server_state.clone();
+                let service = service_fn(move |req| handle(state.clone(), req));
+                tokio::spawn(Http::new().serve_connection(tcp, service));
+            }
+        });
+        Self { addr, state }
+    }
+
+    /// To give the bot instead of Notion's.
+    pub fn url(&self) -> String {
+        format!("http://{}/v1", self.addr)
+    }
+
+    pub fn pages(&self) -> Vec<Page> {
+        let state = self.state.lock().unwrap();
+        state
+            .pages
+            .iter()
+            .map(|p| serde_json::from_value(p.clone()).unwrap())
+            .collect()
+    }
+
+    /// After `ok` more requests succeed, the next ones fail, one for each failure given.
+    pub fn fail_after(&self, ok: usize, failures: &[Failure]) {
+        let mut state = self.state.lock().unwrap();
+        state.failures.extend(std::iter::repeat_n(None, ok));
+        state.failures.extend(failures.iter().copied().map(Some));
+    }
+
+    pub fn received(&self) -> Vec<Received> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    pub fn upload(&self, id: &str) -> Option<Upload> {
+        self.state.lock().unwrap().uploads.get(id).cloned()
+    }
+
+    pub fn people(&self) -> Vec<String> {
+        self.state.lock().unwrap().people.clone()
+    }
+
+    /// Like someone adding an option in Notion.
+    pub fn add_person(&self, name: &str) {
+        self.state.lock().unwrap().people.push(name.to_string());
+    }
+
+    /// Like someone deleting the page in Notion, it can't be written to anymore.
+    pub fn delete_page(&self, id: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.pages.retain(|p| p["id"] != id);
+        state.children.remove(id);
+    }
+
+    pub fn children(&self, id: &str) -> Vec<Block> {
+        let state = self.state.lock().unwrap();
+        state
+            .children
+            .get(id)
+            .into_iter()
+            .flatten()
+            .map(|b| serde_json::from_value(b.clone()).unwrap())
+            .collect()
+    }
+}
+
+async fn handle(
+    state: Arc<Mutex<State>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let content_type = req
+        .headers()
+        .get("Content-Type")
+        .map(|c| c.to_str().unwrap().to_string())
+        .unwrap_or_default();
+    let raw = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let body: Value = if raw.is_empty() || !content_type.starts_with("application/json") {
+        Value::Null
+    } else {
+        serde_json::from_slice(&raw).unwrap()
+    };
+    let segments: Vec<&str> = path.trim_start_matches("/v1/").split('/').collect();
+    let mut state = state.lock().unwrap();
+    state.received.push(Received {
+        method: method.clone(),
+        path: segments.join("/"),
+        at: Instant::now(),
+    });
+    let failure = state.failures.pop_front().flatten();
+    if let Some(failure) = failure
+        && !failure.after_applying
+    {
+        return Ok(failed(failure));
+    }
+    let result = match (&method, segments.as_slice()) {
+        (&Method::GET, ["databases", id]) => retrieve_database(&state, id),
+        (&Method::PATCH, ["databases", id]) => update_database(&mut state, id, &body),
+        (&Method::POST, ["databases", id, "query"]) => query_database(&state, id, &body),
+        (&Method::POST, ["pages"]) => Ok(create_page(&mut state, body)),
+        (&Method::PATCH, ["pages", id]) => update_page(&mut state, id, &body),
+        (&Method::POST, ["file_uploads"]) => Ok(create_upload(&mut state, &body)),
+        (&Method::POST, ["file_uploads", id, "send"]) => {
+            send_upload(&mut state, id, &content_type, &raw)
+        }
+        (&Method::GET, ["blocks", id, "children"]) => list_children(&state, id),
+        (&Method::PATCH, ["blocks", id, "children"]) => append_children(&mut state, id, &body),
+        (&Method::PATCH, ["blocks", id]) => update_block(&mut state, id, &body),
+        (&Method::DELETE, ["blocks", id]) => delete_block(&mut state, id),
+        _ => Err((StatusCode::BAD_REQUEST, "invalid_request_url")),
+    };
+    if let Some(failure) = failure {
+        return Ok(failed(failure));
+    }
+    let (status, body) = match result {
+        Ok(body) => (StatusCode::OK, body),
+        Err((status, code)) => (
+            status,
+            json!({
+                "object": "error",
+                "status": status.as_u16(),
+                "code": code,
+                "message": format!("{method} {path}"),
+            }),
+        ),
+    };
+    Ok(Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap())
+}
+
+fn failed(failure: Failure) -> Response<Body> {
+    let code = match failure.status {
+        409 => "conflict_error",
+        429 => "rate_limited",
+        503 => "service_unavailable",
+        _ => "internal_server_error",
+    };
+    let mut response = Response::builder()
+        .status(failure.status)
+        .header("Content-Type", "application/json");
+    if let Some(seconds) = failure.retry_after {
+        response = response.header("Retry-After", seconds.to_string());
+    }
+    let body = json!({
+        "object": "error",
+        "status": failure.status,
+        "code": code,
+        "message": "Failing on purpose",
+    });
+    response.body(Body::from(body.to_string())).unwrap()
+}
+
+type ApiResult<T> = Result<T, (StatusCode, &'static str)>;
+
+const NOT_FOUND: (StatusCode, &str) = (StatusCode::NOT_FOUND, "object_not_found");
+const VALIDATION_ERROR: (StatusCode, &str) = (StatusCode::BAD_REQUEST, "validation_error");
+
+fn user() -> Value {
+    json!({ "object": "user", "id": "00000000-0000-4000-8000-00000000000f" })
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339()
+}
+
+fn database_properties(state: &State) -> Value {
+    json!({
+        "Name": { "id": "title", "name": "Name", "type": "title", "title": {} },
+        "Date": { "id": "date", "name": "Date", "type": "date", "date": {} },
+        "Mood": { "id": "mood", "name": "Mood", "type": "number", "number": { "format": "number" } },
+        "Tags": { "id": "tags", "name": "Tags", "type": "multi_select", "multi_select": {
+            "options": [{ "name": "Stream of conciousness", "color": "blue" }],
+        } },
+        "Pessoas": { "id": "people", "name": "Pessoas", "type": "multi_select", "multi_select": {
+            "options": state.people.iter().map(|p| json!({ "name": p, "color": "gray" })).collect::<Vec<_>>(),
+        } },
+    })
+}
+
+fn retrieve_database(state: &State, id: &str) -> ApiResult<Value> {
+    if id != DATABASE_ID {
+        return Err(NOT_FOUND);
+    }
+    Ok(json!({
+        "object": "database",
+        "id": DATABASE_ID,
+        "created_time": now(),
+        "last_edited_time": now(),
+        "title": [{
+            "type": "text",
+            "text": { "content": "Journal" },
+            "plain_text": "Journal",
+        }],
+        "description": [],
+        "properties": database_properties(state),
+        "parent": { "type": "workspace", "workspace": true },
+        "url": format!("https://www.notion.so/{}", DATABASE_ID.replace('-', "")),
+        "archived": false,
+        "is_inline": false,
+    }))
+}
+
+fn update_database(state: &mut State, id: &str, body: &Value) -> ApiResult<Value> {
+    // Real Notion rejects nulls here.
+    if body["title"].is_null() || body["description"].is_null() {
+        return Err((StatusCode::BAD_REQUEST, "validation_error"));
+    }
+    if let Some(options) = body["properties"]["Pessoas"]["multi_select"]["options"].as_array() {
+        state.people = options
+            .iter()
+            .map(|o| o["name"].as_str().unwrap().to_string())
+            .collect();
+    }
+    retrieve_database(state, id)
+}
+
+fn date_of(value: &Value) -> Option<&str> {
+    value.as_str().map(|d| &d[..10.min(d.len())])
+}
+
+fn matches_filter(page: &Value, filter: &Value) -> bool {
+    if let Some(and) = filter.get("and").and_then(Value::as_array) {
+        return and.iter().all(|f| matches_filter(page, f));
+    }
+    if let Some(or) = filter.get("or").and_then(Value::as_array) {
+        return or.iter().any(|f| matches_filter(page, f));
+    }
+    let property = &page["properties"][filter["property"].as_str().unwrap()];
+    if let Some(cond) = filter.get("date") {
+        let Some(date) = date_of(&property["date"]["start"]) else {
+            return false;
+        };
+        let (op, value) = cond.as_object().unwrap().iter().next().unwrap();
+        let value = date_of(value).unwrap();
+        match op.as_str() {
+            "equals" => date == value,
+            "after" => date > value,
+            "before" => date < value,
+            "on_or_after" => date >= value,
+            "on_or_before" => date <= value,
+            _ => panic!("Unsupported date filter: {cond}"),
+        }
+    } else if let Some(cond) = filter.get("multi_select") {
+        let contains = cond["contains"].as_str().unwrap();
+        property["multi_select"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|o| o["name"] == contains)
+    } else {
+        panic!("Unsupported filter: {filter}")
+    }
+}
+
+fn query_database(state: &State, id: &str, body: &Value) -> ApiResult<Value> {
+    if id != DATABASE_ID {
+        return Err(NOT_FOUND);
+    }
+    let mut results: Vec<&Value> = state
+        .pages
+        .iter()
+        .filter(|p| p["archived"] == false)
+        .filter(|p| body.get("filter").is_none_or(|f| matches_filter(p, f)))
+        .collect();
+    // Only by dates, the only property the bot sorts by.
+    for sort in body["sorts"].as_array().into_iter().flatten().rev() {
+        let property = sort["property"].as_str().unwrap();
+        results.sort_by_key(|p| date_of(&p["properties"][property]["date"]["start"]));
+        if sort["direction"] == "descending" {
+            results.reverse();
+        }
+    }
+    Ok(json!({
+        "object": "list",
+        "results": results,
+        "next_cursor": null,
+        "has_more": false,
+    }))
+}
+
+/// Real Notion fills the plain text of everything it returns.
+fn fill_plain_text(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            if object.get("type").is_some_and(|t| t == "text")
+                && let Some(content) = object["text"]["content"].as_str()
+            {
+                let content = content.to_string();
+                let href = object["text"]["link"]["url"].clone();
+                object.insert("plain_text".to_string(), content.into());
+                object.insert("href".to_string(), href);
+            }
+            object.values_mut().for_each(fill_plain_text);
+        }
+        Value::Array(array) => array.iter_mut().for_each(fill_plain_text),
+        _ => {}
+    }
+}
+
+/// Real Notion returns all properties of the database, even the ones never set.
+fn empty_property(schema: &Value) -> Value {
+    let kind = schema["type"].as_str().unwrap();
+    let empty = match kind {
+        "title" | "multi_select" => json!([]),
+        _ => Value::Null,
+    };
+    json!({ "id": schema["id"], "type": kind, kind: empty })
+}
+
+fn create_page(state: &mut State, body: Value) -> Value {
+    let id = state.new_id();
+    let mut properties = database_properties(state)
+        .as_object()
+        .unwrap()
+        .iter()
+        .map(|(name, schema)| (name.clone(), empty_property(schema)))
+        .collect::<serde_json::Map<_, _>>();
+    for (name, value) in body["properties"].as_object().unwrap() {
+        // The title can be set by its id too.
+        let name = if name == "title" { "Name" } else { name };
+        properties.insert(name.to_string(), value.clone());
+    }
+    let mut page = json!({
+        "object": "page",
+        "id": id,
+        "created_time": now(),
+        "created_by": user(),
+        "last_edited_time": now(),
+        "last_edited_by": user(),
+        "archived": false,
+        "icon": body["icon"],
+        "properties": properties,
+        "parent": body["parent"],
+        "url": format!("https://www.notion.so/{}", id.replace('-', "")),
+    });
+    fill_plain_text(&mut page);
+    state.pages.push(page.clone());
+    page
+}
+
+fn update_page(state: &mut State, id: &str, body: &Value) -> ApiResult<Value> {
+    let page = state
+        .pages
+        .iter_mut()
+        .find(|p| p["id"] == id)
+        .ok_or(NOT_FOUND)?;
+    for (name, value) in body["properties"].as_object().into_iter().flatten() {
+        page["properties"][name] = value.clone();
+    }
+    fill_plain_text(page);
+    page["last_edited_time"] = now().into();
+    Ok(page.clone())
+}
+
+fn upload_json(id: &str, upload: &Upload) -> Value {
+    json!({
+        "object": "file_upload",
+        "id": id,
+        "filename": upload.filename,
+        "content_type": upload.content_type,
+        "status": if upload.data.is_some() { "uploaded" } else { "pending" },
+    })
+}
+
+fn create_upload(state: &mut State, body: &Value) -> Value {
+    let id = state.new_id();
+    let upload = Upload {
+        filename: body["filename"].as_str().unwrap().to_string(),
+        content_type: body["content_type"].as_str().unwrap().to_string(),
+        data: None,
+    };
+    let json = upload_json(&id, &upload);
+    state.uploads.insert(id, upload);
+    json
+}
+
+/// Only understands a multipart body with a single part, which is all the bot sends.
+fn send_upload(state: &mut State, id: &str, content_type: &str, body: &[u8]) -> ApiResult<Value> {
+    let upload = state.uploads.get_mut(id).ok_or(NOT_FOUND)?;
+    let boundary = content_type
+        .strip_prefix("multipart/form-data; boundary=")
+        .ok_or((StatusCode::BAD_REQUEST, "validation_error"))?;
+    let start = body.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let end_marker = format!("\r\n--{boundary}--");
+    let end = body
+        .windows(end_marker.len())
+        .position(|w| w == end_marker.as_bytes())
+        .unwrap();
+    upload.data = Some(body[start..end].to_vec());
+    Ok(upload_json(id, upload))
+}
+
+/// Real Notion shows uploaded files as hosted files.
+fn resolve_upload(state: &State, block: &mut Value) -> ApiResult<()> {
+    let kind = block["type"].as_str().unwrap().to_string();
+    if block[&kind]["type"] != "file_upload" {
+        return Ok(());
+    }
+    let id = block[&kind]["file_upload"]["id"].as_str().unwrap();
+    let upload = state
+        .uploads
+        .get(id)
+        .filter(|u| u.data.is_some())
+        .ok_or((StatusCode::BAD_REQUEST, "validation_error"))?;
+    block[&kind] = json!({
+        "type": "file",
+        "file": {
+            "url": format!("https://files.example/{}/{}", id, upload.filename),
+            "expiry_time": now(),
+        },
+        "caption": [],
+        "name": upload.filename,
+    });
+    Ok(())
+}
+
+/// All at once, the bot should handle pagination but there are never that many in tests.
+fn list_children(state: &State, id: &str) -> ApiResult<Value> {
+    if !state.pages.iter().any(|p| p["id"] == id) && !state.children.contains_key(id) {
+        return Err(NOT_FOUND);
+    }
+    Ok(json!({
+        "object": "list",
+        "results": state.children.get(id).cloned().unwrap_or_default(),
+        "next_cursor": null,
+        "has_more": false,
+    }))
+}
+
+/// Like Notion, at most 100 texts per block, each up to 2000 characters (in UTF-16).
+fn within_limits(block: &Value) -> bool {
+    let kind = block["type"].as_str().unwrap();
+    let Some(rich_text) = block[kind]["rich_text"].as_array() else {
+        return true;
+    };
+    rich_text.len() <= 100
+        && rich_text.iter().all(|rt| {
+            rt["text"]["content"]
+                .as_str()
+                .is_none_or(|content| content.encode_utf16().count() <= 2000)
+        })
+}
+
+fn append_children(state: &mut State, id: &str, body: &Value) -> ApiResult<Value> {
+    if !state.pages.iter().any(|p| p["id"] == id) && !state.children.contains_key(id) {
+        return Err(NOT_FOUND);
+    }
+    let children = body["children"].as_array().unwrap();
+    if children.len() > 100 || !children.iter().all(within_limits) {
+        return Err(VALIDATION_ERROR);
+    }
+    let mut results = vec![];
+    for child in children {
+        let mut block = child.clone();
+        block["object"] = "block".into();
+        block["id"] = state.new_id().into();
+        block["parent"] = json!({ "type": "page_id", "page_id": id });
+        block["created_time"] = now().into();
+        block["last_edited_time"] = now().into();
+        block["archived"] = false.into();
+        block["has_children"] = false.into();
+        resolve_upload(state, &mut block)?;
+        fill_plain_text(&mut block);
+        results.push(block);
+    }
+    let siblings = state.children.entry(id.to_string()).or_default();
+    let at = match body["after"].as_str() {
+        Some(after) => {
+            siblings
+                .iter()
+                .position(|b| b["id"] == after)
+                .ok_or(VALIDATION_ERROR)?
+                + 1
+        }
+        None => siblings.len(),
+    };
+    siblings.splice(at..at, results.iter().cloned());
+    Ok(json!({
+        "object": "list",
+        "results": results,
+        "next_cursor": null,
+        "has_more": false,
+    }))
+}
+
+fn find_block<'a>(state: &'a mut State, id: &str) -> ApiResult<&'a mut Value> {
+    state
+        .children
+        .values_mut()
+        .flatten()
+        .find(|b| b["id"] == id)
+        .ok_or(NOT_FOUND)
+}
+
+/// Only the content changes, the type must stay the same.
+fn update_block(state: &mut State, id: &str, body: &Value) -> ApiResult<Value> {
+    let block = find_block(state, id)?;
+    let kind = block["type"].as_str().unwrap().to_string();
+    let content = body.get(&kind).ok_or(VALIDATION_ERROR)?;
+    let mut updated = block.clone();
+    updated[&kind] = content.clone();
+    if !within_limits(&updated) {
+        return Err(VALIDATION_ERROR);
+    }
+    fill_plain_text(&mut updated);
+    updated["last_edited_time"] = now().into();
+    *block = updated.clone();
+    Ok(updated)
+}
+
+/// Archived blocks are not listed anymore.
+fn delete_block(state: &mut State, id: &str) -> ApiResult<Value> {
+    let mut block = find_block(state, id)?.clone();
+    for siblings in state.children.values_mut() {
+        siblings.retain(|b| b["id"] != id);
+    }
+    block["archived"] = true.into();
+    Ok(block)
+}
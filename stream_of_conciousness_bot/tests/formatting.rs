@@ -0,0 +1,97 @@
+use stream_of_conciousness_bot::formatting::{Formatting, Part, Span, Style, split};
+use teloxide::types::{MessageEntity, MessageEntityKind};
+
+fn formatting(start: usize, end: usize, style: Style) -> Formatting {
+    Formatting { start, end, style }
+}
+
+#[test]
+fn converts_utf16_offsets_to_bytes() {
+    let text = "😀 bold and @someone";
+    let entities = [
+        MessageEntity::bold(3, 4),
+        MessageEntity::new(MessageEntityKind::Mention, 12, 8),
+        // Not something the journal shows.
+        MessageEntity::new(MessageEntityKind::Hashtag, 3, 4),
+    ];
+    assert_eq!(
+        Formatting::from_entities(text, &entities),
+        [
+            formatting(5, 9, Style::Bold),
+            formatting(14, 22, Style::Link("https://t.me/someone".to_string())),
+        ]
+    );
+}
+
+#[test]
+fn multiline_code_is_a_block() {
+    let text = "run\nthis";
+    let entities = [MessageEntity::code(0, 8)];
+    assert_eq!(
+        Formatting::from_entities(text, &entities),
+        [formatting(0, 8, Style::CodeBlock { language: None })]
+    );
+}
+
+#[test]
+fn nested_styles_are_split_into_spans() {
+    let formatting = [
+        formatting(0, 5, Style::Bold),
+        formatting(3, 5, Style::Italic),
+    ];
+    assert_eq!(
+        split("ab cd!", &formatting),
+        [Part::Paragraph(vec![
+            Span {
+                text: "ab ",
+                styles: vec![&Style::Bold],
+            },
+            Span {
+                text: "cd",
+                styles: vec![&Style::Bold, &Style::Italic],
+            },
+            Span {
+                text: "!",
+                styles: vec![],
+            },
+        ])]
+    );
+}
+
+#[test]
+fn splits_around_code_blocks() {
+    let text = "Look:\nfn main() {}\nnice";
+    let entities = [MessageEntity::pre(Some("rust".to_string()), 6, 12)];
+    let formatting = Formatting::from_entities(text, &entities);
+    let plain = |text| {
+        Part::Paragraph(vec![Span {
+            text,
+            styles: vec![],
+        }])
+    };
+    assert_eq!(
+        split(text, &formatting),
+        [
+            plain("Look:"),
+            Part::CodeBlock {
+                code: "fn main() {}",
+                language: Some("rust"),
+            },
+            plain("nice"),
+        ]
+    );
+    // There's always a paragraph first, for the time.
+    let text = "fn main() {}";
+    let entities = [MessageEntity::pre(None, 0, 12)];
+    let formatting = Formatting::from_entities(text, &entities);
+    assert_eq!(
+        split(text, &formatting),
+        [
+            Part::Paragraph(vec![]),
+            Part::CodeBlock {
+                code: "fn main() {}",
+                language: None,
+            },
+        ]
+    );
+}
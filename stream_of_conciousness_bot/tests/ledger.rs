@@ -0,0 +1,23 @@
+use stream_of_conciousness_bot::ledger::Ledger;
+
+#[test]
+fn remembers_applied_updates_per_user() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("ledger.json");
+    let mut ledger = Ledger::open(path.clone()).unwrap();
+    ledger.record("ana", [10, 11]).unwrap();
+    let ledger = Ledger::open(path).unwrap();
+    assert!(ledger.is_applied("ana", 10));
+    assert!(ledger.is_applied("ana", 11));
+    assert!(!ledger.is_applied("ana", 12));
+    assert!(!ledger.is_applied("bia", 10));
+}
+
+#[test]
+fn forgets_the_oldest_updates() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut ledger = Ledger::open(dir.path().join("ledger.json")).unwrap();
+    ledger.record("ana", 0..5000).unwrap();
+    assert!(!ledger.is_applied("ana", 0));
+    assert!(ledger.is_applied("ana", 4999));
+}
@@ -0,0 +1,109 @@
+use chrono::{NaiveDate, NaiveTime};
+use stream_of_conciousness_bot::{
+    journal::{JournalBackend, TextEntry},
+    local_journal::LocalJournal,
+};
+
+fn date() -> NaiveDate {
+    NaiveDate::from_ymd_opt(2024, 10, 20).unwrap()
+}
+
+fn entry(text: &str, hour: u32, marker: &str) -> TextEntry {
+    TextEntry {
+        marker: Some(marker.to_string()),
+        ..TextEntry::new(
+            text.to_string(),
+            NaiveTime::from_hms_opt(hour, 0, 0).unwrap(),
+        )
+    }
+}
+
+async fn lines(journal: &mut LocalJournal) -> Vec<String> {
+    journal.read_day(date()).await.unwrap().unwrap().lines
+}
+
+#[tokio::test]
+async fn skips_texts_already_added() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut journal = LocalJournal::open(dir.path().join("ana.json")).unwrap();
+    journal
+        .add_text(&[entry("Hi", 10, "1/1")], date())
+        .await
+        .unwrap();
+    // Like replaying the same message, along with a new one.
+    let mut journal = LocalJournal::open(dir.path().join("ana.json")).unwrap();
+    let ids = journal
+        .add_text(&[entry("Hi", 10, "1/1"), entry("Bye", 11, "1/2")], date())
+        .await
+        .unwrap();
+    assert_eq!(ids.keys().collect::<Vec<_>>(), ["1/2"]);
+    assert_eq!(lines(&mut journal).await, ["[10:00] Hi", "[11:00] Bye"]);
+}
+
+#[tokio::test]
+async fn ids_still_work_after_removing_texts() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut journal = LocalJournal::open(dir.path().join("ana.json")).unwrap();
+    let ids = journal
+        .add_text(
+            &[
+                entry("A", 10, "1/1"),
+                entry("B", 11, "1/2"),
+                entry("C", 12, "1/3"),
+            ],
+            date(),
+        )
+        .await
+        .unwrap();
+    journal.remove_blocks(&ids["1/1"], date()).await.unwrap();
+    journal
+        .edit_text(&entry("C, edited", 12, "1/3"), &ids["1/3"], date())
+        .await
+        .unwrap();
+    assert_eq!(
+        lines(&mut journal).await,
+        ["[11:00] B", "[12:00] C, edited"]
+    );
+    // New texts don't get the id of a removed one.
+    let new = journal
+        .add_text(&[entry("D", 13, "1/4")], date())
+        .await
+        .unwrap();
+    assert_ne!(new["1/4"], ids["1/1"]);
+    journal.remove_blocks(&ids["1/2"], date()).await.unwrap();
+    assert_eq!(
+        lines(&mut journal).await,
+        ["[12:00] C, edited", "[13:00] D"]
+    );
+    journal
+        .remove_blocks(&ids["1/1"], date())
+        .await
+        .unwrap_err();
+}
+
+#[tokio::test]
+async fn reads_journals_from_before_text_ids() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("ana.json");
+    std::fs::write(
+        &path,
+        r#"{"people": [], "days": {"2024-10-20": {"mood": null, "people": [],
+            "texts": [["A", "10:00:00"], ["B", "11:00:00"]]}}}"#,
+    )
+    .unwrap();
+    let mut journal = LocalJournal::open(path).unwrap();
+    // Their ids were their index.
+    journal
+        .edit_text(&entry("B, edited", 11, "1/2"), &["1".to_string()], date())
+        .await
+        .unwrap();
+    let ids = journal
+        .add_text(&[entry("C", 12, "1/3")], date())
+        .await
+        .unwrap();
+    assert_eq!(ids["1/3"], ["2"]);
+    assert_eq!(
+        lines(&mut journal).await,
+        ["[10:00] A", "[11:00] B, edited", "[12:00] C"]
+    );
+}
@@ -0,0 +1,46 @@
+use chrono::{Days, Utc};
+use stream_of_conciousness_bot::messages::{Journaled, Messages};
+
+#[test]
+fn remembers_what_messages_became() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("messages.json");
+    let today = Utc::now().date_naive();
+    let mut messages = Messages::open(path.clone()).unwrap();
+    messages
+        .record("ana", "1/9", today, Journaled::Mood(40))
+        .unwrap();
+    messages
+        .record("ana", "1/10", today, Journaled::Mood(70))
+        .unwrap();
+    // Too old to be edited.
+    let old = today.checked_sub_days(Days::new(60)).unwrap();
+    messages
+        .record("ana", "1/1", old, Journaled::Text(vec!["a".to_string()]))
+        .unwrap();
+    let messages = Messages::open(path).unwrap();
+    // By message id, not as text.
+    assert_eq!(messages.latest_mood("ana", today), Some(70));
+    assert_eq!(messages.latest_mood("bia", today), None);
+    assert!(messages.get("ana", "1/1").is_none());
+    assert_eq!(messages.get("ana", "1/9").unwrap().date, today);
+}
+
+#[test]
+fn knows_people_mentioned_in_other_messages() {
+    let dir = tempfile::tempdir().unwrap();
+    let today = Utc::now().date_naive();
+    let mut messages = Messages::open(dir.path().join("messages.json")).unwrap();
+    let people =
+        |names: &[&str]| Journaled::People(names.iter().map(ToString::to_string).collect());
+    messages
+        .record("ana", "1/1", today, people(&["João", "Bia"]))
+        .unwrap();
+    messages
+        .record("ana", "1/2", today, people(&["joao"]))
+        .unwrap();
+    assert!(messages.mentioned_elsewhere("ana", today, "1/1", "João"));
+    assert!(!messages.mentioned_elsewhere("ana", today, "1/1", "Bia"));
+    let yesterday = today.pred_opt().unwrap();
+    assert!(!messages.mentioned_elsewhere("ana", yesterday, "1/1", "João"));
+}
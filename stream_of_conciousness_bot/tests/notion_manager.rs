@@ -0,0 +1,803 @@
+mod fake_notion;
+
+use std::time::Duration;
+
+use chrono::{NaiveDate, NaiveTime};
+use fake_notion::{DATABASE_ID, Failure, FakeNotion};
+use hyper::Method;
+use notion_client::objects::{
+    block::{Block, BlockType, Language},
+    file::File as HostedOrExternal,
+    page::{DateOrDateTime, Page, PageProperty},
+    rich_text::RichText,
+};
+use stream_of_conciousness_bot::{
+    attachment::{AttachmentKind, File},
+    config::PropertyNames,
+    formatting::{Formatting, Style},
+    journal::{Aliases, Day, JournalBackend, TextEntry},
+    notion_manager::NotionManagerForUser,
+    retry::RetryPolicy,
+    search::Search,
+};
+
+async fn setup(people: &[&str]) -> (FakeNotion, NotionManagerForUser) {
+    let fake = FakeNotion::start(people).await;
+    let mut notion = NotionManagerForUser::new(
+        "token",
+        DATABASE_ID,
+        PropertyNames::default(),
+        Some(&fake.url()),
+    )
+    .unwrap();
+    notion.check_can_access().await.unwrap();
+    (fake, notion)
+}
+
+fn date() -> NaiveDate {
+    NaiveDate::from_ymd_opt(2024, 10, 20).unwrap()
+}
+
+fn time(h: u32, m: u32) -> NaiveTime {
+    NaiveTime::from_hms_opt(h, m, 0).unwrap()
+}
+
+/// At 10:00, from the Telegram message in the marker.
+fn entry(text: &str, marker: &str) -> TextEntry {
+    TextEntry {
+        marker: Some(marker.to_string()),
+        ..TextEntry::new(text.to_string(), time(10, 0))
+    }
+}
+
+fn notes() -> File {
+    File {
+        kind: AttachmentKind::File,
+        name: "notes.txt".to_string(),
+        content_type: "text/plain".to_string(),
+        file_id: None,
+        data: b"notes".to_vec(),
+    }
+}
+
+fn multi_select(page: &Page, property: &str) -> Vec<String> {
+    match page.properties.get(property) {
+        Some(PageProperty::MultiSelect { multi_select, .. }) => {
+            multi_select.iter().filter_map(|o| o.name.clone()).collect()
+        }
+        other => panic!("Not a multi select: {other:?}"),
+    }
+}
+
+fn paragraphs(fake: &FakeNotion, page_id: &str) -> Vec<String> {
+    paragraphs_of(&fake.children(page_id))
+}
+
+fn paragraphs_of(blocks: &[Block]) -> Vec<String> {
+    blocks
+        .iter()
+        .map(|b| match &b.block_type {
+            BlockType::Paragraph { paragraph } => paragraph
+                .rich_text
+                .iter()
+                .filter_map(|t| t.plain_text())
+                .collect(),
+            other => panic!("Not a paragraph: {other:?}"),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn fails_for_unknown_database() {
+    let fake = FakeNotion::start(&[]).await;
+    let mut notion =
+        NotionManagerForUser::new("token", "nope", PropertyNames::default(), Some(&fake.url()))
+            .unwrap();
+    assert!(notion.check_can_access().await.is_err());
+}
+
+#[tokio::test]
+async fn creates_day_page_once() {
+    let (fake, mut notion) = setup(&[]).await;
+    let id = notion.get_or_create_day(date()).await.unwrap();
+    assert_eq!(notion.get_or_create_day(date()).await.unwrap(), id);
+
+    // A fresh manager has no cache, so it must find the page by querying.
+    let mut other = NotionManagerForUser::new(
+        "token",
+        DATABASE_ID,
+        PropertyNames::default(),
+        Some(&fake.url()),
+    )
+    .unwrap();
+    assert_eq!(other.get_or_create_day(date()).await.unwrap(), id);
+
+    let pages = fake.pages();
+    assert_eq!(pages.len(), 1);
+    let page = &pages[0];
+    assert_eq!(page.id, id);
+    assert_eq!(multi_select(page, "Tags"), ["Stream of conciousness"]);
+    match page.properties.get("Date") {
+        Some(PageProperty::Date {
+            date: Some(value), ..
+        }) => assert_eq!(value.start, Some(DateOrDateTime::Date(date()))),
+        other => panic!("Wrong date: {other:?}"),
+    }
+    match page.properties.get("Name") {
+        Some(PageProperty::Title { title, .. }) => {
+            assert_eq!(title[0].plain_text().as_deref(), Some("2024-10-20"))
+        }
+        other => panic!("Wrong title: {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn different_days_get_different_pages() {
+    let (fake, mut notion) = setup(&[]).await;
+    let a = notion.get_or_create_day(date()).await.unwrap();
+    let b = notion
+        .get_or_create_day(date().succ_opt().unwrap())
+        .await
+        .unwrap();
+    assert_ne!(a, b);
+    assert_eq!(fake.pages().len(), 2);
+}
+
+#[tokio::test]
+async fn sets_mood() {
+    let (fake, mut notion) = setup(&[]).await;
+    notion.set_mood(70, date()).await.unwrap();
+    notion.set_mood(250, date()).await.unwrap();
+    match fake.pages()[0].properties.get("Mood") {
+        Some(PageProperty::Number { number, .. }) => {
+            assert_eq!(number.as_ref().and_then(|n| n.as_u64()), Some(100))
+        }
+        other => panic!("Wrong mood: {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn restores_previous_mood() {
+    let (fake, mut notion) = setup(&[]).await;
+    assert_eq!(notion.set_mood(70, date()).await.unwrap(), None);
+    assert_eq!(notion.set_mood(40, date()).await.unwrap(), Some(70));
+    notion.restore_mood(Some(70), date()).await.unwrap();
+    let mood = |fake: &FakeNotion| match fake.pages()[0].properties.get("Mood") {
+        Some(PageProperty::Number { number, .. }) => number.as_ref().and_then(|n| n.as_u64()),
+        other => panic!("Wrong mood: {other:?}"),
+    };
+    assert_eq!(mood(&fake), Some(70));
+    notion.restore_mood(None, date()).await.unwrap();
+    assert_eq!(mood(&fake), None);
+}
+
+#[tokio::test]
+async fn adds_text_as_timestamped_paragraphs() {
+    let (fake, mut notion) = setup(&[]).await;
+    notion
+        .add_text(
+            &[
+                TextEntry::new("First".to_string(), time(22, 5)),
+                TextEntry::new("Second".to_string(), time(1, 30)),
+            ],
+            date(),
+        )
+        .await
+        .unwrap();
+    notion
+        .add_text(&[TextEntry::new("Third".to_string(), time(2, 0))], date())
+        .await
+        .unwrap();
+    let page_id = &fake.pages()[0].id;
+    assert_eq!(
+        paragraphs(&fake, page_id),
+        ["[22:05] First", "[01:30] Second", "[02:00] Third"]
+    );
+}
+
+#[tokio::test]
+async fn keeps_telegram_formatting() {
+    let (fake, mut notion) = setup(&[]).await;
+    let entry = TextEntry {
+        formatting: vec![
+            Formatting {
+                start: 3,
+                end: 7,
+                style: Style::Bold,
+            },
+            Formatting {
+                start: 8,
+                end: 12,
+                style: Style::Link("https://example.com".to_string()),
+            },
+            Formatting {
+                start: 13,
+                end: 24,
+                style: Style::CodeBlock {
+                    language: Some("Rust".to_string()),
+                },
+            },
+        ],
+        ..TextEntry::new("Hi bold link\nfn main(){}\nbye".to_string(), time(10, 0))
+    };
+    notion.add_text(&[entry], date()).await.unwrap();
+    let blocks = fake.children(&fake.pages()[0].id);
+    let [first, code, last] = blocks.as_slice() else {
+        panic!("Expected three blocks: {blocks:?}");
+    };
+    let BlockType::Paragraph { paragraph } = &first.block_type else {
+        panic!("Not a paragraph: {first:?}");
+    };
+    let texts: Vec<_> = paragraph
+        .rich_text
+        .iter()
+        .map(|t| match t {
+            RichText::Text {
+                text, annotations, ..
+            } => (
+                text.content.as_str(),
+                annotations.as_ref().is_some_and(|a| a.bold),
+                text.link.as_ref().map(|l| l.url.as_str()),
+            ),
+            other => panic!("Not text: {other:?}"),
+        })
+        .collect();
+    assert_eq!(
+        texts,
+        [
+            ("[10:00]", false, None),
+            (" Hi ", false, None),
+            ("bold", true, None),
+            (" ", false, None),
+            ("link", false, Some("https://example.com")),
+        ]
+    );
+    let BlockType::Code { code } = &code.block_type else {
+        panic!("Not code: {code:?}");
+    };
+    assert_eq!(code.language, Language::Rust);
+    assert_eq!(code.rich_text[0].plain_text().unwrap(), "fn main(){}");
+    assert_eq!(paragraphs_of(std::slice::from_ref(last)), ["bye"]);
+}
+
+#[tokio::test]
+async fn splits_long_texts() {
+    let (fake, mut notion) = setup(&[]).await;
+    // Emojis are two characters for Notion.
+    let long = "word 😀 ".repeat(1000);
+    notion
+        .add_text(&[TextEntry::new(long.clone(), time(10, 0))], date())
+        .await
+        .unwrap();
+    assert_eq!(
+        paragraphs(&fake, &fake.pages()[0].id),
+        [format!("[10:00] {}", long)]
+    );
+}
+
+#[tokio::test]
+async fn appends_big_batches_in_order() {
+    let (fake, mut notion) = setup(&[]).await;
+    let entries: Vec<_> = (0..250)
+        .map(|i| TextEntry {
+            marker: Some(format!("1/{}", i)),
+            ..TextEntry::new(format!("Entry {}", i), time(10, 0))
+        })
+        .collect();
+    let ids = notion.add_text(&entries, date()).await.unwrap();
+    assert_eq!(ids.len(), 250);
+    let expected: Vec<_> = (0..250).map(|i| format!("[10:00] Entry {}", i)).collect();
+    assert_eq!(paragraphs(&fake, &fake.pages()[0].id), expected);
+}
+
+#[tokio::test]
+async fn edits_text_in_place() {
+    let (fake, mut notion) = setup(&[]).await;
+    let ids = notion
+        .add_text(&[entry("First", "1/1"), entry("Second", "1/2")], date())
+        .await
+        .unwrap();
+    let code = TextEntry {
+        formatting: vec![Formatting {
+            start: 6,
+            end: 10,
+            style: Style::CodeBlock { language: None },
+        }],
+        ..entry(
+            "Fixed
+code
+after",
+            "1/1",
+        )
+    };
+    let new_ids = notion.edit_text(&code, &ids["1/1"], date()).await.unwrap();
+    assert_eq!(new_ids.len(), 3);
+    assert_eq!(new_ids[0], ids["1/1"][0]);
+    let page_id = &fake.pages()[0].id;
+    let blocks = fake.children(page_id);
+    assert!(matches!(blocks[1].block_type, BlockType::Code { .. }));
+    assert_eq!(paragraphs_of(&blocks[2..]), ["after", "[10:00] Second"]);
+    // Shorter again, the extra blocks go away.
+    notion
+        .edit_text(&entry("Short", "1/1"), &new_ids, date())
+        .await
+        .unwrap();
+    assert_eq!(
+        paragraphs(&fake, page_id),
+        ["[10:00] Short", "[10:00] Second"]
+    );
+}
+
+#[tokio::test]
+async fn removes_blocks() {
+    let (fake, mut notion) = setup(&[]).await;
+    let ids = notion
+        .add_text(&[entry("First", "1/1"), entry("Oops", "1/2")], date())
+        .await
+        .unwrap();
+    let file = notes();
+    let file_id = notion.add_file(&file, date()).await.unwrap();
+    notion.remove_blocks(&[file_id], date()).await.unwrap();
+    notion.remove_blocks(&ids["1/2"], date()).await.unwrap();
+    assert_eq!(paragraphs(&fake, &fake.pages()[0].id), ["[10:00] First"]);
+}
+
+#[tokio::test]
+async fn reads_day_back() {
+    let (fake, mut notion) = setup(&["Ana"]).await;
+    assert_eq!(notion.read_day(date()).await.unwrap(), None);
+    // Reading doesn't create the page.
+    assert!(fake.pages().is_empty());
+    notion.set_mood(70, date()).await.unwrap();
+    notion
+        .add_people(&["ana".to_string()], &Aliases::new(), date())
+        .await
+        .unwrap();
+    let code = TextEntry {
+        formatting: vec![Formatting {
+            start: 4,
+            end: 9,
+            style: Style::CodeBlock { language: None },
+        }],
+        ..TextEntry::new("See\nls -l".to_string(), time(9, 15))
+    };
+    notion.add_text(&[code], date()).await.unwrap();
+    let file = notes();
+    notion.add_file(&file, date()).await.unwrap();
+    let day = notion.read_day(date()).await.unwrap().unwrap();
+    assert_eq!(
+        day,
+        Day {
+            date: date(),
+            mood: Some(70),
+            people: vec!["Ana".to_string()],
+            tags: vec!["Stream of conciousness".to_string()],
+            lines: vec![
+                "[09:15] See".to_string(),
+                "ls -l".to_string(),
+                "📎 notes.txt".to_string(),
+            ],
+        }
+    );
+    assert!(
+        day.to_string()
+            .starts_with("📅 Sunday, 2024-10-20\nMood: 70\n")
+    );
+}
+
+#[tokio::test]
+async fn searches_past_entries() {
+    let (fake, mut notion) = setup(&[]).await;
+    let day = |d| NaiveDate::from_ymd_opt(2024, 10, d).unwrap();
+    for (d, text) in [
+        (1, "Café with Ana"),
+        (2, "Nothing to see"),
+        (3, "More coffee, then cafe again"),
+    ] {
+        notion
+            .add_text(&[TextEntry::new(text.to_string(), time(9, 0))], day(d))
+            .await
+            .unwrap();
+    }
+    let hits = notion
+        .search(&Search::parse("CAFÉ").unwrap())
+        .await
+        .unwrap();
+    let found: Vec<_> = hits.iter().map(|h| (h.date, h.snippet.as_str())).collect();
+    assert_eq!(
+        found,
+        [
+            (day(3), "[09:00] More coffee, then cafe again"),
+            (day(1), "[09:00] Café with Ana"),
+        ]
+    );
+    // Pages are created in order.
+    assert_eq!(hits[0].url.as_ref(), Some(&fake.pages()[2].url));
+    let since = notion
+        .search(&Search::parse("cafe since:2024-10-02").unwrap())
+        .await
+        .unwrap();
+    assert_eq!(since.len(), 1);
+    assert_eq!(since[0].date, day(3));
+}
+
+#[tokio::test]
+async fn reads_moods_in_range() {
+    let (_fake, mut notion) = setup(&[]).await;
+    let day = |d| NaiveDate::from_ymd_opt(2024, 10, d).unwrap();
+    for (d, mood) in [(5, 70), (1, 40), (3, 55), (9, 90)] {
+        notion.set_mood(mood, day(d)).await.unwrap();
+    }
+    // A day without mood is skipped.
+    notion.get_or_create_day(day(4)).await.unwrap();
+    let moods = notion.moods(day(2), day(5)).await.unwrap();
+    assert_eq!(moods, [(day(3), 55), (day(5), 70)]);
+}
+
+#[tokio::test]
+async fn exports_all_days() {
+    let (_fake, mut notion) = setup(&[]).await;
+    let day = |d| NaiveDate::from_ymd_opt(2024, 10, d).unwrap();
+    notion
+        .add_text(&[TextEntry::new("Later".to_string(), time(9, 0))], day(5))
+        .await
+        .unwrap();
+    notion.set_mood(40, day(1)).await.unwrap();
+    let days = notion.all_days().await.unwrap();
+    let found: Vec<_> = days
+        .iter()
+        .map(|d| (d.date, d.mood, d.lines.clone()))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            (day(1), Some(40), vec![]),
+            (day(5), None, vec!["[09:00] Later".to_string()]),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn uploads_files_as_blocks() {
+    let (fake, mut notion) = setup(&[]).await;
+    let file = File {
+        kind: AttachmentKind::Image,
+        name: "photo.jpg".to_string(),
+        content_type: "image/jpeg".to_string(),
+        file_id: None,
+        data: b"not really a jpeg".to_vec(),
+    };
+    notion.add_file(&file, date()).await.unwrap();
+    let page_id = &fake.pages()[0].id;
+    let blocks = fake.children(page_id);
+    let [block] = blocks.as_slice() else {
+        panic!("Expected one block: {blocks:?}");
+    };
+    let BlockType::Image { image } = &block.block_type else {
+        panic!("Not an image: {block:?}");
+    };
+    let HostedOrExternal::File { file: hosted } = &image.file_type else {
+        panic!("Not uploaded: {image:?}");
+    };
+    // The fake puts the upload id in the url.
+    let upload_id = hosted.url.split('/').nth(3).unwrap();
+    let upload = fake.upload(upload_id).unwrap();
+    assert_eq!(upload.filename, "photo.jpg");
+    assert_eq!(upload.content_type, "image/jpeg");
+    assert_eq!(upload.data.unwrap(), file.data);
+}
+
+#[tokio::test]
+async fn adds_matching_people() {
+    let (fake, mut notion) = setup(&[
+        "Beatriz Souza",
+        "João Silva",
+        "Pedro Silva",
+        "Ana",
+        "Ana Maria",
+    ])
+    .await;
+    let result = notion
+        .add_people(
+            &[
+                "joao".to_string(),
+                "Nobody".to_string(),
+                "Silva".to_string(),
+            ],
+            &Aliases::new(),
+            date(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(result.added, ["João Silva"]);
+    assert_eq!(result.not_found, ["Nobody"]);
+    assert_eq!(
+        result.ambiguous,
+        [(
+            "Silva".to_string(),
+            vec!["João Silva".to_string(), "Pedro Silva".to_string()]
+        )]
+    );
+    notion
+        .add_people(
+            &["Beatriz".to_string(), "Ana".to_string()],
+            &Aliases::new(),
+            date(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        multi_select(&fake.pages()[0], "Pessoas"),
+        ["Ana", "Beatriz Souza", "João Silva"]
+    );
+}
+
+#[tokio::test]
+async fn removes_people() {
+    let (fake, mut notion) = setup(&["Ana", "Beatriz Souza", "João Silva"]).await;
+    let aliases = Aliases::from([("bia".to_string(), "Beatriz Souza".to_string())]);
+    notion
+        .add_people(
+            &["Ana".to_string(), "Bia".to_string(), "João".to_string()],
+            &aliases,
+            date(),
+        )
+        .await
+        .unwrap();
+    let added = notion
+        .add_people(&["Ana".to_string(), "Pedro".to_string()], &aliases, date())
+        .await
+        .unwrap();
+    // Only the ones that weren't there, to undo it.
+    assert!(added.new.is_empty());
+    let removed = notion
+        .remove_people(&["bia".to_string(), "Nobody".to_string()], &aliases, date())
+        .await
+        .unwrap();
+    assert_eq!(removed, ["Beatriz Souza"]);
+    assert_eq!(
+        multi_select(&fake.pages()[0], "Pessoas"),
+        ["Ana", "João Silva"]
+    );
+}
+
+#[tokio::test]
+async fn adds_new_person_to_database() {
+    let (fake, mut notion) = setup(&["Ana"]).await;
+    notion.add_new_person("Zé Carlos", date()).await.unwrap();
+    assert_eq!(fake.people(), ["Ana", "Zé Carlos"]);
+    assert_eq!(multi_select(&fake.pages()[0], "Pessoas"), ["Zé Carlos"]);
+    // Known from now on, and adding again doesn't duplicate the option.
+    let result = notion
+        .add_people(&["ze".to_string()], &Aliases::new(), date())
+        .await
+        .unwrap();
+    assert_eq!(result.added, ["Zé Carlos"]);
+    notion.add_new_person("Zé Carlos", date()).await.unwrap();
+    assert_eq!(fake.people(), ["Ana", "Zé Carlos"]);
+}
+
+#[tokio::test]
+async fn sees_people_added_in_notion_after_a_while() {
+    let (fake, mut notion) = setup(&["Ana"]).await;
+    let bia = ["Bia".to_string()];
+    notion
+        .add_people(&bia, &Aliases::new(), date())
+        .await
+        .unwrap();
+    fake.add_person("Bia");
+    let result = notion
+        .add_people(&bia, &Aliases::new(), date())
+        .await
+        .unwrap();
+    assert_eq!(result.not_found, ["Bia"]);
+    let mut notion = notion.with_people_ttl(Duration::ZERO);
+    notion.check_can_access().await.unwrap();
+    let result = notion
+        .add_people(&bia, &Aliases::new(), date())
+        .await
+        .unwrap();
+    assert_eq!(result.added, ["Bia"]);
+    assert_eq!(multi_select(&fake.pages()[0], "Pessoas"), ["Bia"]);
+}
+
+#[tokio::test]
+async fn writes_to_a_new_page_after_forgetting_a_deleted_one() {
+    let (fake, mut notion) = setup(&[]).await;
+    notion
+        .add_text(&[entry("First", "1/1")], date())
+        .await
+        .unwrap();
+    fake.delete_page(&fake.pages()[0].id);
+    // Still cached.
+    notion
+        .add_text(&[entry("Second", "1/2")], date())
+        .await
+        .unwrap_err();
+    notion.forget_day(date());
+    notion
+        .add_text(&[entry("Second", "1/2")], date())
+        .await
+        .unwrap();
+    assert_eq!(paragraphs(&fake, &fake.pages()[0].id), ["[10:00] Second"]);
+}
+
+#[tokio::test]
+async fn aliases_are_checked_before_fuzzy_matching() {
+    let (fake, mut notion) = setup(&["Beatriz Souza", "Bianca", "João Silva"]).await;
+    let aliases = Aliases::from([
+        ("bia".to_string(), "Beatriz Souza".to_string()),
+        // Targets are matched too, so they can be short.
+        ("joca".to_string(), "joao".to_string()),
+    ]);
+    let result = notion
+        .add_people(
+            &["Bia".to_string(), "Joca".to_string(), "Bianca".to_string()],
+            &aliases,
+            date(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(result.added, ["Beatriz Souza", "João Silva", "Bianca"]);
+    assert_eq!(
+        multi_select(&fake.pages()[0], "Pessoas"),
+        ["Beatriz Souza", "Bianca", "João Silva"]
+    );
+}
+
+fn retry_policy(base_delay: Duration) -> RetryPolicy {
+    RetryPolicy {
+        base_delay,
+        ..Default::default()
+    }
+}
+
+fn failure(status: u16) -> Failure {
+    Failure {
+        status,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn retries_transient_errors() {
+    let (fake, notion) = setup(&[]).await;
+    let mut notion = notion.with_retry_policy(retry_policy(Duration::from_millis(10)));
+    let day = notion.get_or_create_day(date()).await.unwrap();
+    // Updating the mood twice is fine, so even when it might have worked.
+    let maybe_applied = Failure {
+        after_applying: true,
+        ..failure(502)
+    };
+    fake.fail_after(0, &[failure(429), failure(503), maybe_applied]);
+    notion.set_mood(70, date()).await.unwrap();
+    let page = fake.pages().into_iter().find(|p| p.id == day).unwrap();
+    match page.properties.get("Mood") {
+        Some(PageProperty::Number { number, .. }) => {
+            assert_eq!(number.as_ref().and_then(|n| n.as_u64()), Some(70))
+        }
+        other => panic!("Wrong mood: {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn honours_retry_after() {
+    let (fake, notion) = setup(&[]).await;
+    // Way longer than the test waits.
+    let mut notion = notion.with_retry_policy(retry_policy(Duration::from_secs(60)));
+    notion.get_or_create_day(date()).await.unwrap();
+    let rate_limited = Failure {
+        retry_after: Some(0),
+        ..failure(429)
+    };
+    fake.fail_after(0, &[rate_limited]);
+    let file = notes();
+    tokio::time::timeout(Duration::from_secs(10), notion.add_file(&file, date()))
+        .await
+        .expect("Retry-After was not used")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn honours_retry_after_when_adding_text() {
+    let (fake, notion) = setup(&[]).await;
+    let mut notion = notion.with_retry_policy(retry_policy(Duration::from_secs(60)));
+    let day = notion.get_or_create_day(date()).await.unwrap();
+    let rate_limited = Failure {
+        retry_after: Some(1),
+        ..failure(429)
+    };
+    fake.fail_after(0, &[rate_limited]);
+    let entries = [entry("Hi", "1/1")];
+    tokio::time::timeout(Duration::from_secs(10), notion.add_text(&entries, date()))
+        .await
+        .expect("Retry-After was not used")
+        .unwrap();
+    assert_eq!(paragraphs(&fake, &day), ["[10:00] Hi"]);
+    let appends: Vec<_> = fake
+        .received()
+        .into_iter()
+        .filter(|r| r.method == Method::PATCH && r.path == format!("blocks/{}/children", day))
+        .collect();
+    let [limited, retried] = &appends[..] else {
+        panic!("Not appended twice: {appends:?}");
+    };
+    assert!(retried.at - limited.at >= Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn caps_retry_after() {
+    let (fake, notion) = setup(&[]).await;
+    let mut notion = notion.with_retry_policy(RetryPolicy {
+        max_delay: Duration::from_millis(10),
+        ..retry_policy(Duration::from_millis(10))
+    });
+    notion.get_or_create_day(date()).await.unwrap();
+    let rate_limited = Failure {
+        retry_after: Some(3600),
+        ..failure(429)
+    };
+    fake.fail_after(0, &[rate_limited]);
+    tokio::time::timeout(Duration::from_secs(10), notion.set_mood(70, date()))
+        .await
+        .expect("Waited as long as Notion asked")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn gives_up_on_other_errors() {
+    let (fake, notion) = setup(&[]).await;
+    let mut notion = notion.with_retry_policy(retry_policy(Duration::from_millis(10)));
+    fake.fail_after(0, &[failure(400)]);
+    notion
+        .add_text(&[TextEntry::new("Hi".to_string(), time(10, 0))], date())
+        .await
+        .unwrap_err();
+    // Only the failed query was made.
+    assert!(fake.pages().is_empty());
+}
+
+#[tokio::test]
+async fn does_not_repeat_what_might_have_worked() {
+    let (fake, notion) = setup(&[]).await;
+    let mut notion = notion.with_retry_policy(retry_policy(Duration::from_millis(10)));
+    let lost = Failure {
+        after_applying: true,
+        ..failure(502)
+    };
+    // The query works, the page is created but the response lost.
+    fake.fail_after(1, &[lost]);
+    notion.get_or_create_day(date()).await.unwrap_err();
+    assert_eq!(fake.pages().len(), 1);
+    let day = notion.get_or_create_day(date()).await.unwrap();
+    assert_eq!(fake.pages().len(), 1);
+    notion
+        .add_text(&[TextEntry::new("Hi".to_string(), time(10, 0))], date())
+        .await
+        .unwrap();
+    fake.fail_after(0, &[lost]);
+    notion
+        .add_text(&[TextEntry::new("Again".to_string(), time(10, 5))], date())
+        .await
+        .unwrap_err();
+    assert_eq!(paragraphs(&fake, &day), ["[10:00] Hi", "[10:05] Again"]);
+}
+
+#[tokio::test]
+async fn does_not_repeat_appends_when_told_to_retry_after() {
+    let (fake, notion) = setup(&[]).await;
+    let mut notion = notion.with_retry_policy(retry_policy(Duration::from_millis(10)));
+    let day = notion.get_or_create_day(date()).await.unwrap();
+    let unavailable = Failure {
+        retry_after: Some(0),
+        after_applying: true,
+        ..failure(503)
+    };
+    // The append is done, but answered with a 503.
+    fake.fail_after(0, &[unavailable]);
+    notion
+        .add_text(&[entry("Hi", "1/1")], date())
+        .await
+        .unwrap_err();
+    assert_eq!(paragraphs(&fake, &day), ["[10:00] Hi"]);
+}
@@ -0,0 +1,75 @@
+use chrono::{Duration, NaiveDate, Utc};
+use stream_of_conciousness_bot::{
+    attachment::{AttachmentKind, File},
+    notion_manager::{InnerCommand, NotionCommand},
+    outbox::{MAX_ATTEMPTS, Outbox},
+};
+use teloxide::types::ChatId;
+
+fn cmd(inner: InnerCommand) -> NotionCommand {
+    NotionCommand {
+        date: NaiveDate::from_ymd_opt(2024, 10, 20).unwrap(),
+        username: "user".to_string(),
+        chat_id: ChatId(42),
+        inner,
+        update_ids: vec![7],
+    }
+}
+
+#[test]
+fn retries_with_backoff_then_gives_up() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut outbox = Outbox::open(dir.path().join("outbox.json")).unwrap();
+    let mut now = Utc::now();
+    outbox
+        .add(
+            [(cmd(InnerCommand::Mood(50)), anyhow::anyhow!("down"))],
+            now,
+        )
+        .unwrap();
+    assert!(outbox.take_due(now).is_empty());
+    let mut delays = vec![];
+    loop {
+        let next = outbox.pending()[0].next_attempt;
+        delays.push((next - now).num_minutes());
+        now = next;
+        let [entry] = <[_; 1]>::try_from(outbox.take_due(now)).unwrap();
+        if let Some(dead) = outbox.failed_again(entry, &anyhow::anyhow!("still down"), now) {
+            assert_eq!(dead.attempts, MAX_ATTEMPTS);
+            assert_eq!(dead.last_error, "still down");
+            break;
+        }
+    }
+    assert_eq!(delays, [2, 4, 8, 16, 32]);
+    assert!(outbox.pending().is_empty());
+    assert_eq!(outbox.dead().len(), 1);
+}
+
+#[test]
+fn survives_restarts() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("outbox.json");
+    let now = Utc::now();
+    let file = File {
+        kind: AttachmentKind::Image,
+        name: "photo.jpg".to_string(),
+        content_type: "image/jpeg".to_string(),
+        file_id: Some("AgACAgEAAxkBAAIB".to_string()),
+        data: vec![0, 1, 2, 255],
+    };
+    Outbox::open(path.clone())
+        .unwrap()
+        .add(
+            [(cmd(InnerCommand::File(file, None)), anyhow::anyhow!("down"))],
+            now,
+        )
+        .unwrap();
+    let mut outbox = Outbox::open(path).unwrap();
+    let [entry] = <[_; 1]>::try_from(outbox.take_due(now + Duration::hours(1))).unwrap();
+    let InnerCommand::File(file, None) = entry.cmd.inner else {
+        panic!("Not a file: {:?}", entry.cmd);
+    };
+    // Downloaded again when retrying instead.
+    assert!(file.data.is_empty());
+    assert_eq!(file.file_id.as_deref(), Some("AgACAgEAAxkBAAIB"));
+}
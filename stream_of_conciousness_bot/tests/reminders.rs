@@ -0,0 +1,49 @@
+use chrono::{NaiveDate, NaiveTime};
+use stream_of_conciousness_bot::reminders::{Reminder, Reminders, is_due};
+use teloxide::types::ChatId;
+
+fn time(h: u32, m: u32) -> NaiveTime {
+    NaiveTime::from_hms_opt(h, m, 0).unwrap()
+}
+
+#[test]
+fn parses_reminders() {
+    assert_eq!(
+        "21:00".parse::<Reminder>().unwrap(),
+        Reminder::At(time(21, 0))
+    );
+    assert_eq!(" OFF ".parse::<Reminder>().unwrap(), Reminder::Off);
+    assert!("9pm".parse::<Reminder>().is_err());
+    let json = serde_json::to_string(&Reminder::At(time(7, 30))).unwrap();
+    assert_eq!(json, "\"07:30\"");
+    assert_eq!(
+        serde_json::from_str::<Reminder>(&json).unwrap(),
+        Reminder::At(time(7, 30))
+    );
+}
+
+#[test]
+fn is_due_from_the_day_start() {
+    assert!(!is_due(time(21, 0), time(20, 59), 6));
+    assert!(is_due(time(21, 0), time(21, 0), 6));
+    // Still the same day until 6am.
+    assert!(is_due(time(21, 0), time(2, 0), 6));
+    assert!(!is_due(time(21, 0), time(6, 0), 6));
+    // After midnight, but before the day start, is the end of the day.
+    assert!(!is_due(time(1, 0), time(22, 0), 6));
+    assert!(is_due(time(1, 0), time(1, 30), 6));
+}
+
+#[test]
+fn remembers_chat_and_last_day() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("reminders.json");
+    let mut reminders = Reminders::open(path.clone()).unwrap();
+    let date = NaiveDate::from_ymd_opt(2024, 10, 20).unwrap();
+    reminders.set_chat("ana", ChatId(42)).unwrap();
+    reminders.set_last_day("ana", Some(date)).unwrap();
+    let reminders = Reminders::open(path).unwrap();
+    assert_eq!(reminders.chat("ana"), Some(ChatId(42)));
+    assert_eq!(reminders.last_day("ana"), Some(date));
+    assert_eq!(reminders.chat("bia"), None);
+}
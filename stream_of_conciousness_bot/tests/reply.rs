@@ -0,0 +1,10 @@
+use stream_of_conciousness_bot::reply::split_message;
+
+#[test]
+fn splits_long_messages_at_lines() {
+    let text = "one\ntwo\nthree\n";
+    assert_eq!(split_message(text, 100), [text]);
+    assert_eq!(split_message(text, 9), ["one\ntwo\n", "three\n"]);
+    // Lines too long on their own are cut, counting characters and not bytes.
+    assert_eq!(split_message("ééééé\nok", 2), ["éé", "éé", "é\n", "ok"]);
+}
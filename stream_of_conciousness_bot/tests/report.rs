@@ -0,0 +1,35 @@
+use anyhow::anyhow;
+use stream_of_conciousness_bot::report::PollReport;
+
+#[test]
+fn merges_batches() {
+    let mut report = PollReport::default();
+    report.user("ana").updates += 2;
+    report.user("ana").success();
+    report.unknown_user("stranger".to_string());
+    let mut batch = PollReport::default();
+    batch.user("ana").updates += 1;
+    batch
+        .user("ana")
+        .failure("mood 50", &anyhow!("Notion is down"));
+    batch.user("bia").skipped += 1;
+    batch.unknown_user("stranger".to_string());
+    assert!(batch.none_succeeded());
+    report.merge(batch);
+    let ana = &report.users["ana"];
+    assert_eq!((ana.updates, ana.commands, ana.successes), (3, 2, 1));
+    assert_eq!(ana.failures, ["mood 50: Notion is down"]);
+    assert_eq!(report.users["bia"].skipped, 1);
+    assert_eq!(report.unknown_users["stranger"], 2);
+    assert!(report.has_failures() && !report.none_succeeded());
+}
+
+#[test]
+fn serializes_as_json() {
+    let mut report = PollReport::default();
+    report.user("ana").success();
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["users"]["ana"]["successes"], 1);
+    assert_eq!(json["users"]["ana"]["failures"], serde_json::json!([]));
+    assert_eq!(json["all_failed"], false);
+}
@@ -0,0 +1,47 @@
+use chrono::NaiveDate;
+use stream_of_conciousness_bot::search::{Search, results};
+
+#[test]
+fn parses_terms_and_since() {
+    let search = Search::parse("Café  Ana since:2026-10-01").unwrap();
+    assert_eq!(search.terms, ["cafe", "ana"]);
+    assert_eq!(search.since, NaiveDate::from_ymd_opt(2026, 10, 1));
+    assert!(Search::parse("since:2026-10-01").is_err());
+    assert!(Search::parse("cafe since:yesterday").is_err());
+}
+
+#[test]
+fn finds_lines_with_all_terms_ignoring_accents() {
+    let search = Search::parse("joao cafe").unwrap();
+    let lines = [
+        "[10:00] Café com João".to_string(),
+        "[11:00] Só café".to_string(),
+    ];
+    let found: Vec<_> = search.find(&lines).collect();
+    assert_eq!(found, ["[10:00] Café com João"]);
+}
+
+#[test]
+fn cuts_snippets_around_the_first_term() {
+    let search = Search::parse("needle").unwrap();
+    let line = format!("{}needle{}", "á".repeat(100), "b".repeat(200));
+    let found: Vec<_> = search.find(std::slice::from_ref(&line)).collect();
+    assert_eq!(
+        found,
+        [format!("…{}needle{}…", "á".repeat(60), "b".repeat(134))]
+    );
+}
+
+#[test]
+fn searches_the_last_days_by_default_and_says_so() {
+    let today = NaiveDate::from_ymd_opt(2026, 10, 31).unwrap();
+    let search = Search::parse("cafe").unwrap();
+    assert_eq!(
+        search.with_default_since(today).since,
+        NaiveDate::from_ymd_opt(2026, 10, 1)
+    );
+    assert!(results(&search, &[]).ends_with("add since:2026-01-01 to search further back."));
+    let search = Search::parse("cafe since:2026-01-01").unwrap();
+    assert_eq!(search.with_default_since(today).since, search.since);
+    assert_eq!(results(&search, &[]), "🔎 Nothing found for cafe");
+}
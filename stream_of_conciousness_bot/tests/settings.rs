@@ -0,0 +1,47 @@
+use stream_of_conciousness_bot::{
+    config::Config,
+    settings::{DEFAULT_DAY_START_HOUR, DEFAULT_TIMEZONE, Settings},
+};
+
+fn config() -> Config {
+    toml::from_str(
+        r#"
+        [[users]]
+        username = "ana"
+        timezone = "Europe/London"
+        day_start_hour = 4
+        [[users]]
+        username = "bia"
+        "#,
+    )
+    .unwrap()
+}
+
+#[test]
+fn changed_then_configured_then_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut settings = Settings::load(&config(), dir.path()).unwrap();
+    assert_eq!(settings.timezone("ana"), chrono_tz::Europe::London);
+    assert_eq!(settings.day_start_hour("ana"), 4);
+    assert_eq!(settings.timezone("bia"), DEFAULT_TIMEZONE);
+    assert_eq!(settings.day_start_hour("bia"), DEFAULT_DAY_START_HOUR);
+    settings.set_timezone("ana", "Asia/Tokyo").unwrap();
+    settings.set_day_start_hour("bia", 0).unwrap();
+    // Kept after a restart, and only what changed.
+    let settings = Settings::load(&config(), dir.path()).unwrap();
+    assert_eq!(settings.timezone("ana"), chrono_tz::Asia::Tokyo);
+    assert_eq!(settings.day_start_hour("ana"), 4);
+    assert_eq!(settings.timezone("bia"), DEFAULT_TIMEZONE);
+    assert_eq!(settings.day_start_hour("bia"), 0);
+}
+
+#[test]
+fn invalid_changes_are_not_saved() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut settings = Settings::load(&config(), dir.path()).unwrap();
+    settings.set_timezone("ana", "Mars/Olympus").unwrap_err();
+    settings.set_day_start_hour("ana", 24).unwrap_err();
+    assert_eq!(settings.timezone("ana"), chrono_tz::Europe::London);
+    assert_eq!(settings.day_start_hour("ana"), 4);
+    assert!(!dir.path().join("settings.json").exists());
+}
@@ -0,0 +1,38 @@
+use chrono::{NaiveDate, Weekday};
+use stream_of_conciousness_bot::stats::{MoodStats, Period, chart};
+
+fn day(d: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2024, 10, d).unwrap()
+}
+
+#[test]
+fn parses_periods() {
+    assert_eq!(Period::parse(""), Ok(Period::Month));
+    assert_eq!(Period::parse(" Week "), Ok(Period::Week));
+    assert_eq!(Period::parse("year"), Ok(Period::Year));
+    assert!(Period::parse("decade").is_err());
+    assert_eq!(Period::Week.range(day(20)), (day(14), day(20)));
+}
+
+#[test]
+fn computes_mood_stats() {
+    // 2024-10-14 is a Monday.
+    let moods = [(day(14), 40), (day(15), 50), (day(21), 80), (day(22), 70)];
+    let stats = MoodStats::compute(Period::Month, &moods).unwrap();
+    assert_eq!(stats.mean, 60.0);
+    assert_eq!(stats.min, (day(14), 40));
+    assert_eq!(stats.max, (day(21), 80));
+    assert!((stats.trend - 30.0).abs() < 1.0, "{}", stats.trend);
+    assert_eq!(stats.weekdays, [(Weekday::Mon, 60.0), (Weekday::Tue, 60.0)]);
+    let caption = stats.to_string();
+    assert!(caption.contains("Mean: 60.0"), "{}", caption);
+    assert!(caption.contains("↗️"), "{}", caption);
+    assert!(MoodStats::compute(Period::Week, &[]).is_none());
+}
+
+#[test]
+fn draws_a_png() {
+    let moods = [(day(14), 40), (day(20), 80)];
+    let png = chart(Period::Week, Period::Week.range(day(20)), &moods).unwrap();
+    assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+}
@@ -0,0 +1,49 @@
+use chrono::NaiveDate;
+use stream_of_conciousness_bot::undo::{Undo, UndoLog};
+
+fn date() -> NaiveDate {
+    NaiveDate::from_ymd_opt(2024, 10, 20).unwrap()
+}
+
+fn ids(ids: &[&str]) -> Vec<String> {
+    ids.iter().map(ToString::to_string).collect()
+}
+
+#[test]
+fn undoes_the_last_write_first() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("undo.json");
+    let mut log = UndoLog::open(path.clone()).unwrap();
+    log.push("ana", date(), Undo::Mood(Some(40))).unwrap();
+    log.push("ana", date(), Undo::People(ids(&["Bia"])))
+        .unwrap();
+    // Nothing new, nothing to undo.
+    log.push("ana", date(), Undo::People(vec![])).unwrap();
+    let mut log = UndoLog::open(path).unwrap();
+    assert_eq!(log.last("ana").unwrap().undo, Undo::People(ids(&["Bia"])));
+    log.pop("ana").unwrap();
+    assert_eq!(log.last("ana").unwrap().undo, Undo::Mood(Some(40)));
+    log.pop("ana").unwrap();
+    assert!(log.last("ana").is_none());
+    assert!(log.last("bia").is_none());
+}
+
+#[test]
+fn follows_edited_texts() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut log = UndoLog::open(dir.path().join("undo.json")).unwrap();
+    let file = Undo::Blocks {
+        ids: ids(&["file", "caption"]),
+        marker: Some("1/1".to_string()),
+    };
+    log.push("ana", date(), file).unwrap();
+    log.edited("ana", "1/1", &ids(&["caption"]), &ids(&["caption", "code"]))
+        .unwrap();
+    assert_eq!(
+        log.last("ana").unwrap().undo,
+        Undo::Blocks {
+            ids: ids(&["file", "caption", "code"]),
+            marker: Some("1/1".to_string()),
+        }
+    );
+}
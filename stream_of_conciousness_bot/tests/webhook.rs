@@ -0,0 +1,43 @@
+use stream_of_conciousness_bot::webhook::{SECRET_TOKEN_HEADER, parse_update, verify_secret};
+use teloxide::types::UpdateKind;
+
+fn payload(name: &str) -> Vec<u8> {
+    std::fs::read(format!("{}/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+}
+
+#[test]
+fn checks_the_secret() {
+    assert!(verify_secret("s3cret", Some("s3cret")));
+    assert!(!verify_secret("s3cret", Some("s3creT")));
+    assert!(!verify_secret("s3cret", Some("s3cret2")));
+    assert!(!verify_secret("s3cret", None));
+    // An empty secret would let anyone in.
+    assert!(!verify_secret("", Some("")));
+}
+
+#[test]
+fn parses_recorded_updates() {
+    let update = parse_update(&payload("tests/webhook/text_message.json")).unwrap();
+    assert_eq!(update.id.0, 10000);
+    let UpdateKind::Message(msg) = update.kind else {
+        panic!("Not a message: {:?}", update.kind);
+    };
+    assert_eq!(msg.text(), Some("Coffee with Bia"));
+    let update = parse_update(&payload("tests/webhook/mood_callback.json")).unwrap();
+    let UpdateKind::CallbackQuery(query) = update.kind else {
+        panic!("Not a callback: {:?}", update.kind);
+    };
+    assert_eq!(query.data.as_deref(), Some("mood 2024-10-20 70"));
+    assert!(parse_update(b"{\"not\": \"an update\"}").is_err());
+}
+
+/// The event `cargo lambda invoke` sends to the webhook Lambda locally.
+#[test]
+fn parses_recorded_lambda_event() {
+    let event: serde_json::Value =
+        serde_json::from_slice(&payload("lambda_executor/events/webhook.json")).unwrap();
+    let secret = event["headers"][SECRET_TOKEN_HEADER.to_lowercase()].as_str();
+    assert!(verify_secret("test-secret", secret));
+    let update = parse_update(event["body"].as_str().unwrap().as_bytes()).unwrap();
+    assert!(matches!(update.kind, UpdateKind::Message(_)));
+}